@@ -0,0 +1,124 @@
+#ifndef RELAXED_IK_CORE_H
+#define RELAXED_IK_CORE_H
+
+/* Generated by cbindgen from src/relaxed_ik_wrapper.rs, do not edit. See that file for the memory ownership rules. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct RelaxedIK RelaxedIK;
+
+typedef struct IKLink IKLink;
+
+/**
+ * How a solve finished, see ExitReason
+ */
+typedef enum SolveExitReason {
+  SolveExitReason_Converged,
+  SolveExitReason_MaxIterations,
+  SolveExitReason_OutOfTime,
+  /**
+   * the solver stopped making progress short of the tolerance
+   */
+  SolveExitReason_Stalled,
+  /**
+   * the solution was not finite and the previous solution was returned
+   */
+  SolveExitReason_NotFinite,
+} SolveExitReason;
+
+/**
+ * An array of f64
+ */
+typedef struct Opt {
+  const double *data;
+  int length;
+} Opt;
+
+/**
+ * Outcome of a solve, see SolveResult
+ */
+typedef struct SolveStatus {
+  bool converged;
+  enum SolveExitReason exit_reason;
+  int iterations;
+  /**
+   * cost at the returned solution, NaN if the solver failed
+   */
+  double cost;
+} SolveStatus;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Loads a solver from a settings file, e.g. configs/settings.yaml. Returns NULL if the settings
+ * cannot be loaded.
+ */
+RelaxedIK *relaxed_ik_new(const char *path_to_setting);
+
+void relaxed_ik_free(RelaxedIK *ptr);
+
+int relaxed_ik_num_dofs(const RelaxedIK *ptr);
+
+int relaxed_ik_num_chains(const RelaxedIK *ptr);
+
+/**
+ * Name of the joint of a dof, in the order of the solutions. Release with relaxed_ik_free_string.
+ */
+char *relaxed_ik_joint_name(const RelaxedIK *ptr, int dof);
+
+/**
+ * Sets the end effector goals of all chains in the world frame: num_chains positions (x, y, z),
+ * num_chains quaternions (x, y, z, w) and, unless tolerances is NULL, num_chains tolerances
+ * (x, y, z, rx, ry, rz). Returns 0, or -1 if num_chains does not match the robot.
+ */
+int relaxed_ik_set_goals(RelaxedIK *ptr,
+                         const double *positions,
+                         const double *quats,
+                         const double *tolerances,
+                         int num_chains);
+
+/**
+ * Solves for the current goals, with the velocity limits if constrain_velocity is nonzero, and
+ * returns the joint values. Release with relaxed_ik_free_opt. Unless status is NULL, it is set to
+ * how the solve finished; a solve that is not converged may return the previous solution. status
+ * is left unchanged if the returned Opt is empty.
+ */
+struct Opt relaxed_ik_solve(RelaxedIK *ptr, int constrain_velocity, struct SolveStatus *status);
+
+/**
+ * Resets the solver to a configuration of num_dofs joint values. Returns 0, or -1 if length
+ * does not match the robot.
+ */
+int relaxed_ik_reset(RelaxedIK *ptr, const double *x, int length);
+
+/**
+ * Loads the robot configs/example_settings/<robot_name>.yaml, relative to the working
+ * directory. Returns NULL if the robot cannot be loaded.
+ */
+IKLink *iklink_new(const char *robot_name);
+
+void iklink_free(IKLink *ptr);
+
+int iklink_num_dofs(const IKLink *ptr);
+
+/**
+ * Tracks num_poses end effector poses (t, x, y, z, qx, qy, qz, qw), in the layout of the
+ * trajectories in input_trajectories. Returns the motion as num_poses rows of
+ * (t, joint values), i.e. num_poses * (1 + num_dofs) values. Release with relaxed_ik_free_opt.
+ */
+struct Opt iklink_solve(IKLink *ptr, const double *poses, int num_poses);
+
+void relaxed_ik_free_opt(struct Opt opt);
+
+void relaxed_ik_free_string(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RELAXED_IK_CORE_H */
@@ -0,0 +1,56 @@
+use glob::glob;
+use yaml_rust::YamlLoader;
+use relaxed_ik_lib::utils_rust::file_utils::{*};
+use relaxed_ik_lib::groove::vars::RelaxedIKVars;
+use relaxed_ik_lib::spacetime::analytic_ik::AnalyticIK;
+
+// Solves for the end effector poses of random configurations of the example robots with analytic
+// IK and checks that every solution reaches its pose. Also reports how many links of each robot
+// use the collision geometry of their urdf rather than a capsule, see mesh_path in settings.yaml.
+
+fn main() {
+    let path_to_src = get_path_to_src();
+    let num_samples = 500;
+    let mut rng = rand::thread_rng();
+    let mut num_failed = 0;
+    let (mut num_links, mut num_urdf_links) = (0, 0);
+
+    for entry in glob(&(path_to_src.clone() + "configs/example_settings/*.yaml")).unwrap() {
+        let path = entry.unwrap();
+        let settings = YamlLoader::load_from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let urdf = settings[0]["urdf"].as_str().unwrap();
+        if !std::path::Path::new(&(path_to_src.clone() + "configs/urdfs/" + urdf)).exists() {
+            println!("Skipping {}: {} not found", path.display(), urdf);
+            continue;
+        }
+        let v = RelaxedIKVars::from_local_settings(path.to_str().unwrap());
+        let name = path.file_name().unwrap().to_str().unwrap();
+
+        let links = &v.self_collision.links;
+        let urdf_links = links.iter().filter(|l| !l.approximated).count();
+        println!("{}: collision geometry of {} of {} links loaded from the urdf, {} approximated by capsules",
+            name, urdf_links, links.len(), links.len() - urdf_links);
+        num_links += links.len();
+        num_urdf_links += urdf_links;
+
+        let analytic_ik = match AnalyticIK::from_robot(&v.robot) {
+            Some(analytic_ik) => analytic_ik,
+            None => {
+                println!("{}: no analytic IK", name);
+                continue;
+            }
+        };
+        let (num_solutions, num_missed, max_error) = analytic_ik.check_round_trips(&v.robot, num_samples, &mut rng);
+        let status = if num_missed == 0 && num_solutions > 0 { "ok" } else { num_failed += 1; "FAILED" };
+        println!("{}: {:?}, {} solutions for {} poses, {} miss their pose, max error {:.3e}  {}",
+            name, analytic_ik.layout, num_solutions, num_samples, num_missed, max_error, status);
+    }
+
+    println!("{} of {} links use their urdf collision geometry", num_urdf_links, num_links);
+
+    if num_failed > 0 {
+        println!("{} robots have analytic IK solutions that miss their pose", num_failed);
+        std::process::exit(1);
+    }
+    println!("All analytic IK solutions reach their pose");
+}
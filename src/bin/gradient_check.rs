@@ -0,0 +1,208 @@
+use glob::glob;
+use rand::Rng;
+use yaml_rust::YamlLoader;
+use nalgebra::{Vector3, Vector6, Isometry3};
+use parry3d_f64::shape::SharedShape;
+use relaxed_ik_lib::utils_rust::file_utils::{*};
+use relaxed_ik_lib::groove::vars::RelaxedIKVars;
+use relaxed_ik_lib::groove::objective::{*};
+use relaxed_ik_lib::groove::objective_master::ObjectiveMaster;
+use relaxed_ik_lib::utils_rust::yaml_utils::{ObjectiveSpec, ObjectiveOptions};
+use relaxed_ik_lib::groove::env_collision::CollisionObstacle;
+
+// Compares the analytic and automatic gradients of the objectives to central differences
+// on random configurations, goals and tolerances of the example robots
+
+fn random_config(v: &RelaxedIKVars, rng: &mut rand::prelude::ThreadRng) -> Vec<f64> {
+    let mut x = Vec::with_capacity(v.robot.num_dofs);
+    for i in 0..v.robot.num_dofs {
+        let l = v.robot.joints[i].lower_limit.max(-std::f64::consts::PI);
+        let u = v.robot.joints[i].upper_limit.min(std::f64::consts::PI);
+        x.push(rng.gen_range(l..=u));
+    }
+    x
+}
+
+fn random_tolerance(rng: &mut rand::prelude::ThreadRng) -> f64 {
+    // exercise both the exact and the tolerance branch of the losses
+    match rng.gen_range(0..3) {
+        0 => 0.0,
+        1 => rng.gen_range(0.02..0.5),
+        _ => std::f64::consts::PI
+    }
+}
+
+// Returns the largest error of the analytic gradient (if the objective has one) and of the
+// automatic gradient, relative to the largest central difference. Joints where the forward and
+// backward differences disagree are skipped, the objective is not smooth there (e.g. a collision
+// pair entering the proximity distance).
+fn check_objective(name: &str, objective: &dyn ObjectiveTrait, x: &[f64], v: &RelaxedIKVars, h: f64) -> (f64, f64) {
+    let f = |u: &[f64]| -> f64 {
+        objective.call(u, v, &KinematicState::new(&v.robot, u, objective.needs()))
+    };
+    let f_0 = f(x);
+
+    let mut numeric = vec![0.0; x.len()];
+    let mut smooth = vec![true; x.len()];
+    for i in 0..x.len() {
+        let mut x_h = x.to_vec();
+        x_h[i] = x[i] + h;
+        let f_f = f(&x_h);
+        x_h[i] = x[i] - h;
+        let f_b = f(&x_h);
+        numeric[i] = (f_f - f_b) / (2.0 * h);
+        smooth[i] = ((f_f - f_0) / h - (f_0 - f_b) / h).abs() < 0.1 * numeric[i].abs().max(1.0);
+    }
+    let scale = numeric.iter().fold(1.0, |acc: f64, n| acc.max(n.abs()));
+    let max_error = |grad: &[f64]| -> f64 {
+        let mut max_error: f64 = 0.0;
+        for i in 0..x.len() {
+            if smooth[i] {
+                let error = (grad[i] - numeric[i]).abs() / scale;
+                max_error = max_error.max(error);
+            }
+        }
+        max_error
+    };
+
+    let mut analytic_error = 0.0;
+    if objective.gradient_type() == GradientType::Analytic {
+        let state = KinematicState::new(&v.robot, x, objective.gradient_needs());
+        let (f_analytic, grad) = objective.gradient(x, v, &state);
+        assert!((f_analytic - f_0).abs() < 1e-9, "{}: value returned by gradient does not match call", name);
+        analytic_error = max_error(&grad);
+    }
+
+    let (f_dual, grad) = autodiff_gradient(objective, x, v);
+    assert!((f_dual - f_0).abs() < 1e-9 * f_0.abs().max(1.0), "{}: value returned by call_dual does not match call", name);
+    (analytic_error, max_error(&grad))
+}
+
+// Largest error of the gradient of an objective master relative to central differences of its value
+fn check_master(master: &ObjectiveMaster, x: &[f64], v: &RelaxedIKVars, h: f64) -> f64 {
+    let (f_0, grad) = master.gradient(x, v);
+    assert!((f_0 - master.call(x, v)).abs() < 1e-9 * f_0.abs().max(1.0), "value returned by ObjectiveMaster::gradient does not match call");
+    let mut max_error: f64 = 0.0;
+    for i in 0..x.len() {
+        let mut x_h = x.to_vec();
+        x_h[i] = x[i] + h;
+        let f_f = master.call(&x_h, v);
+        x_h[i] = x[i] - h;
+        let f_b = master.call(&x_h, v);
+        let numeric = (f_f - f_b) / (2.0 * h);
+        max_error = max_error.max((grad[i] - numeric).abs() / numeric.abs().max(1.0));
+    }
+    max_error
+}
+
+fn main() {
+    let path_to_src = get_path_to_src();
+    let num_samples = 200;
+    // parry distances are only accurate to about 1e-7, so collision objectives are
+    // compared to central differences with a larger step and tolerance
+    let (h, threshold) = (1e-6, 1e-4);
+    let (collision_h, collision_threshold) = (1e-4, 5e-2);
+    let master_threshold = 1e-4;
+    let mut rng = rand::thread_rng();
+    let mut num_failed = 0;
+
+    for entry in glob(&(path_to_src.clone() + "configs/example_settings/*.yaml")).unwrap() {
+        let path = entry.unwrap();
+        let settings = YamlLoader::load_from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let urdf = settings[0]["urdf"].as_str().unwrap();
+        if !std::path::Path::new(&(path_to_src.clone() + "configs/urdfs/" + urdf)).exists() {
+            println!("Skipping {}: {} not found", path.display(), urdf);
+            continue;
+        }
+        let mut v = RelaxedIKVars::from_local_settings(path.to_str().unwrap());
+        v.compute_allowed_collision_matrix();
+        let num_chains = v.robot.num_chains;
+
+        let mut objectives: Vec<(String, Box<dyn ObjectiveTrait>, bool)> = Vec::new();
+        for arm_idx in 0..num_chains {
+            for axis in 0..3 {
+                objectives.push((format!("MatchEEPosiDoF({}, {})", arm_idx, axis), Box::new(MatchEEPosiDoF::new(arm_idx, axis)), false));
+                objectives.push((format!("MatchEERotaDoF({}, {})", arm_idx, axis), Box::new(MatchEERotaDoF::new(arm_idx, axis)), false));
+            }
+            objectives.push((format!("MatchEEPosGoals({})", arm_idx), Box::new(MatchEEPosGoals::new(arm_idx)), false));
+            objectives.push((format!("MatchEEQuatGoals({})", arm_idx), Box::new(MatchEEQuatGoals::new(arm_idx)), false));
+            objectives.push((format!("LookAtPoint({})", arm_idx), Box::new(LookAtPoint::new(arm_idx)), false));
+            objectives.push((format!("LookAtDistance({})", arm_idx), Box::new(LookAtDistance::new(arm_idx)), false));
+            objectives.push((format!("ViewCone({})", arm_idx), Box::new(ViewCone::new(arm_idx)), false));
+        }
+        objectives.push(("MinimizeVelocity".to_string(), Box::new(MinimizeVelocity::new()), false));
+        objectives.push(("MinimizeAcceleration".to_string(), Box::new(MinimizeAcceleration::new()), false));
+        objectives.push(("MinimizeJerk".to_string(), Box::new(MinimizeJerk::new()), false));
+        for joint_idx in 0..v.robot.num_dofs {
+            objectives.push((format!("EachJointLimits({})", joint_idx), Box::new(EachJointLimits::new(joint_idx)), false));
+        }
+        objectives.push(("Posture".to_string(), Box::new(Posture::new()), false));
+        objectives.push(("SelfCollision".to_string(), Box::new(SelfCollision::new()), true));
+
+        // obstacles around the initial end effector poses for the environment collision objective
+        for arm_idx in 0..num_chains {
+            let p = v.init_ee_positions[arm_idx];
+            v.env_collision.obstacles.push(CollisionObstacle::new(format!("sphere_{}", arm_idx), SharedShape::ball(0.1),
+                Isometry3::translation(p.x + 0.15, p.y, p.z), false));
+            v.env_collision.obstacles.push(CollisionObstacle::new(format!("box_{}", arm_idx), SharedShape::cuboid(0.2, 0.2, 0.02),
+                Isometry3::translation(p.x, p.y, p.z - 0.25), false));
+            objectives.push((format!("EnvCollision({})", arm_idx), Box::new(EnvCollision::new(arm_idx)), true));
+        }
+
+        // the master mixes analytic, automatic and finite difference gradients
+        let master = ObjectiveMaster::builder(&v.robot.chain_indices, false)
+            .specs(&ObjectiveSpec::default_relaxed_ik(&ObjectiveOptions{joint_limit_avoidance: true, posture: true, ..ObjectiveOptions::default()}))
+            .objective("manipulability", Box::new(MaximizeManipulability::new()), 1.0)
+            .build();
+        let mut master_error: f64 = 0.0;
+
+        let mut max_errors = vec![(0.0, 0.0); objectives.len()];
+        for _ in 0..num_samples {
+            let x = random_config(&v, &mut rng);
+            v.xopt = random_config(&v, &mut rng);
+            v.prev_state = random_config(&v, &mut rng);
+            v.prev_state2 = random_config(&v, &mut rng);
+            let goal = v.robot.get_ee_pos_and_quat_immutable(&random_config(&v, &mut rng));
+            for arm_idx in 0..num_chains {
+                v.goal_positions[arm_idx] = goal[arm_idx].0;
+                v.goal_quats[arm_idx] = goal[arm_idx].1;
+                v.tolerances[arm_idx] = Vector6::new(random_tolerance(&mut rng), random_tolerance(&mut rng), random_tolerance(&mut rng),
+                    random_tolerance(&mut rng), random_tolerance(&mut rng), random_tolerance(&mut rng));
+                v.goal_look_at_points[arm_idx] = goal[arm_idx].0 + Vector3::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+                let min_distance = rng.gen_range(0.05..0.5);
+                v.look_at_distance_bounds[arm_idx] = (min_distance, min_distance + rng.gen_range(0.1..0.5));
+                v.look_at_cone_angles[arm_idx] = rng.gen_range(0.05..1.0);
+            }
+            v.nominal_config = random_config(&v, &mut rng);
+            v.posture_weights = (0..v.robot.num_dofs).map(|_| rng.gen_range(0.0..2.0)).collect();
+
+            // in collision the penalties are clamped and differences are dominated by rounding
+            let in_collision = v.self_collision.check_collision(&v.robot.get_frames_immutable(&x));
+            for (k, (name, objective, collision)) in objectives.iter().enumerate() {
+                if *collision && in_collision {
+                    continue;
+                }
+                let step = if *collision { collision_h } else { h };
+                let (analytic_error, autodiff_error) = check_objective(name, objective.as_ref(), &x, &v, step);
+                max_errors[k].0 = analytic_error.max(max_errors[k].0);
+                max_errors[k].1 = autodiff_error.max(max_errors[k].1);
+            }
+            master_error = master_error.max(check_master(&master, &x, &v, h));
+        }
+
+        println!("{}:", path.file_name().unwrap().to_str().unwrap());
+        for (k, (name, _, collision)) in objectives.iter().enumerate() {
+            let tol = if *collision { collision_threshold } else { threshold };
+            let status = if max_errors[k].0 < tol && max_errors[k].1 < tol { "ok" } else { num_failed += 1; "FAILED" };
+            println!("    {:<28} max error analytic {:.3e} autodiff {:.3e}  {}", name, max_errors[k].0, max_errors[k].1, status);
+        }
+        let status = if master_error < master_threshold { "ok" } else { num_failed += 1; "FAILED" };
+        println!("    {:<28} max error {:.3e}  {}", "ObjectiveMaster", master_error, status);
+    }
+
+    if num_failed > 0 {
+        println!("{} gradients differ from central differences", num_failed);
+        std::process::exit(1);
+    }
+    println!("All analytic and automatic gradients match central differences");
+}
@@ -16,7 +16,7 @@ fn main() {
             // gradually move along the y axis
             relaxed_ik.vars.goal_positions[j] += Vector3::new(0.0, 0.01, 0.0);
         }
-        let x = relaxed_ik.solve(true);
-        println!("Joint solutions: {:?}", x);
+        let (x, result) = relaxed_ik.solve(true);
+        println!("Joint solutions: {:?} ({:?} after {} iterations)", x, result.exit_reason, result.iterations);
     }
 }
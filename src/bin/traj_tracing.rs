@@ -1,8 +1,10 @@
 
 use glob::glob;
 use csv;
-use nalgebra::{Vector3, UnitQuaternion, Quaternion};
+use nalgebra::{Vector3, UnitQuaternion, Quaternion, Isometry3};
+use yaml_rust::YamlLoader;
 use relaxed_ik_lib::utils_rust::file_utils::{*};
+use relaxed_ik_lib::utils_rust::yaml_utils::yaml_to_pose;
 use relaxed_ik_lib::iklink::IKLink;
 use relaxed_ik_lib::spacetime::motion::Motion;
 
@@ -21,6 +23,14 @@ fn load_traj(path: &str) -> Vec<(f64, Vector3<f64>, UnitQuaternion<f64>)> {
     traj
 }
 
+// A trajectory may come with a yaml file of the same name that gives the pose of the frame it is in:
+// workpiece_pose: {translation: [x, y, z], rotation: [roll, pitch, yaw]}
+fn load_workpiece_pose(path: &str) -> Option<Isometry3<f64>> {
+    let contents = std::fs::read_to_string(path.trim_end_matches(".csv").to_string() + ".yaml").ok()?;
+    let docs = YamlLoader::load_from_str(&contents).unwrap();
+    yaml_to_pose(&docs[0]["workpiece_pose"])
+}
+
 fn save_motion(filename: &str,  motion: Motion) {
     // save motion to a csv file
     let mut wtr = csv::Writer::from_path(filename).unwrap();
@@ -31,15 +41,22 @@ fn save_motion(filename: &str,  motion: Motion) {
     for i in 0..motion.joint_names.len() {
         row.push(robot_name.clone().to_string() + "-" + &motion.joint_names[i] );
     }
+    let has_branches = motion.branch_labels.iter().any(|label| !label.flags.is_empty());
+    if has_branches {
+        row.push("branch".to_string());
+    }
     wtr.write_record(&row).unwrap();
 
     // write data
-    for (time, config) in motion.data.iter() {
+    for (k, (time, config)) in motion.data.iter().enumerate() {
         let mut row = vec![time.to_string()];
 
         for i in config.iter() {
             row.push(i.to_string());
         }
+        if has_branches {
+            row.push(motion.branch_labels[k].to_string());
+        }
         wtr.write_record(&row).unwrap();
     }
 }
@@ -59,6 +76,9 @@ fn main() {
                 let robot_name = file_name.split('_').next().unwrap();
 
                 let mut ik_link = IKLink::new(robot_name, traj);
+                if let Some(workpiece_pose) = load_workpiece_pose(path_str) {
+                    ik_link.workpiece_pose = workpiece_pose;
+                }
                 let motion = ik_link.solve();
 
                 let output_dir = path_to_src.clone() + "output_motions/" + file_name;
@@ -1,168 +1,120 @@
 use crate::utils_rust::yaml_utils::EnvCollisionFileParser;
 use nalgebra::{Vector3, Isometry3, Point3};
-use nalgebra::geometry::{Translation3, UnitQuaternion, Quaternion};
-use ncollide3d::pipeline::{*};
-use ncollide3d::shape::{*};
-use std::collections::BTreeMap;
+use nalgebra::geometry::{Translation3, UnitQuaternion};
+use parry3d_f64::shape::{Segment, SharedShape};
+use parry3d_f64::query;
+use parry3d_f64::query::ClosestPoints;
+use crate::utils_rust::autodiff::{Real, vec3_from_f64, vec3_value, vec3_norm};
+use crate::groove::self_collision::project_point;
 
-#[derive(Clone, Debug)]
-pub struct LinkData {
-    pub is_link: bool,
-    pub arm_idx: i32,
-}
-
-impl LinkData {
-    pub fn new(is_link: bool, arm_idx: i32) -> LinkData {
-        Self {
-            is_link: is_link,
-            arm_idx: arm_idx,
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct CollisionObjectData {
+#[derive(Clone)]
+pub struct CollisionObstacle {
     pub name: String,
-    pub link_data: LinkData,
+    pub shape: SharedShape,
+    pub position: Isometry3<f64>,
+    pub is_dynamic: bool,
 }
 
-impl CollisionObjectData {
-    pub fn new(name: String, link_data: LinkData) -> CollisionObjectData {
+impl CollisionObstacle {
+    pub fn new(name: String, shape: SharedShape, position: Isometry3<f64>, is_dynamic: bool) -> CollisionObstacle {
         Self {
-            name: name,
-            link_data: link_data,
+            name,
+            shape,
+            position,
+            is_dynamic,
         }
     }
 }
 
+#[derive(Clone)]
 pub struct RelaxedIKEnvCollision {
-    pub world: CollisionWorld<f64, CollisionObjectData>,
+    pub obstacles: Vec<CollisionObstacle>,
     pub link_radius: f64,
-    pub link_handles: Vec<Vec<CollisionObjectSlabHandle>>,
-    pub dyn_obstacle_handles: Vec<(CollisionObjectSlabHandle, String)>,
-    pub active_pairs: Vec<BTreeMap<CollisionObjectSlabHandle, Vec<CollisionObjectSlabHandle>>>,
-    pub active_obstacles: Vec<Vec<(Option<CollisionObjectSlabHandle>, f64)>>,
+    // obstacles farther than this from a link are ignored by the collision objective
+    pub proximity_distance: f64,
 }
 
 impl RelaxedIKEnvCollision {
     pub fn init_collision_world (
         env_collision_file: EnvCollisionFileParser,
-        frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>,
     ) -> Self {
         let link_radius = env_collision_file.robot_link_radius;
         let plane_obstacles = env_collision_file.cuboids;
         let sphere_obstacles = env_collision_file.spheres;
         let pcd_obstacles = env_collision_file.pcds;
 
-        // The links are part of group 1 and can only interact with obstacles
-        let mut link_groups = CollisionGroups::new();
-        link_groups.set_membership(&[1]);
-        link_groups.set_blacklist(&[1]);
-        link_groups.set_whitelist(&[2]);
-
-        // All the other objects are part of the group 2 and interact only with the links
-        let mut others_groups = CollisionGroups::new();
-        others_groups.set_membership(&[2]);
-        others_groups.set_blacklist(&[2]);
-        others_groups.set_whitelist(&[1]);
-
-        let proximity_query = GeometricQueryType::Proximity(20.0 * link_radius);
-
-        let mut world = CollisionWorld::new(0.0);
-        let mut link_handles: Vec<Vec<CollisionObjectSlabHandle>> = Vec::new();
-        let mut active_pairs: Vec<BTreeMap<CollisionObjectSlabHandle, Vec<CollisionObjectSlabHandle>>> = Vec::new();
-        let mut active_obstacles: Vec<Vec<(Option<CollisionObjectSlabHandle>, f64)>> = Vec::new();
-        for arm_idx in 0..frames.len() {
-            let mut handles: Vec<CollisionObjectSlabHandle> = Vec::new();
-            let mut obstacles: Vec<(Option<CollisionObjectSlabHandle>, f64)> = Vec::new();
-            let pair: BTreeMap<CollisionObjectSlabHandle, Vec<CollisionObjectSlabHandle>> = BTreeMap::new();
-            let last_elem = frames[arm_idx].0.len() - 1;
-            for i in 0..last_elem {
-                let start_pt = Point3::from(frames[arm_idx].0[i]);
-                let end_pt = Point3::from(frames[arm_idx].0[i + 1]);
-                let segment = ShapeHandle::new(Segment::new(start_pt, end_pt));
-                let segment_pos = nalgebra::one();
-                let link_data = CollisionObjectData::new(format!("Link {}", i), LinkData::new(true, arm_idx as i32));
-                let handle = world.add(segment_pos, segment, link_groups, proximity_query, link_data);
-                handles.push(handle.0);
-                obstacles.push((None, 0.0));
-            }
-            link_handles.push(handles);
-            active_pairs.push(pair);
-            active_obstacles.push(obstacles);
-        }
-
-        let mut dyn_obstacle_handles: Vec<(CollisionObjectSlabHandle, String)> = Vec::new();
-        for i in 0..plane_obstacles.len() {
-            let plane_obs = &plane_obstacles[i];
-            let half_extents = Vector3::new(plane_obs.x_halflength, plane_obs.y_halflength, plane_obs.z_halflength);
-            let plane = ShapeHandle::new(Cuboid::new(half_extents));
+        let mut obstacles: Vec<CollisionObstacle> = Vec::new();
+        for plane_obs in &plane_obstacles {
+            let plane = SharedShape::cuboid(plane_obs.x_halflength, plane_obs.y_halflength, plane_obs.z_halflength);
             let plane_ts = Translation3::new(plane_obs.tx, plane_obs.ty, plane_obs.tz);
             let plane_rot = UnitQuaternion::from_euler_angles(plane_obs.rx, plane_obs.ry, plane_obs.rz);
             let plane_pos = Isometry3::from_parts(plane_ts, plane_rot);
-            let plane_data = CollisionObjectData::new(plane_obs.name.clone(), LinkData::new(false, -1));
-            let plane_handle = world.add(plane_pos, plane, others_groups, proximity_query, plane_data);
-            if plane_obs.is_dynamic {
-                dyn_obstacle_handles.push((plane_handle.0, plane_handle.1.data().name.clone()));
-            }
+            obstacles.push(CollisionObstacle::new(plane_obs.name.clone(), plane, plane_pos, plane_obs.is_dynamic));
         }
 
-        for i in 0..sphere_obstacles.len() {
-            let sphere_obs = &sphere_obstacles[i];
-            let sphere = ShapeHandle::new(Ball::new(sphere_obs.radius));
+        for sphere_obs in &sphere_obstacles {
+            let sphere = SharedShape::ball(sphere_obs.radius);
             let sphere_ts = Translation3::new(sphere_obs.tx, sphere_obs.ty, sphere_obs.tz);
-            let sphere_rot = UnitQuaternion::identity();
-            let sphere_pos = Isometry3::from_parts(sphere_ts, sphere_rot);
-            let sphere_data = CollisionObjectData::new(sphere_obs.name.clone(), LinkData::new(false, -1));
-            let sphere_handle = world.add(sphere_pos, sphere, others_groups, proximity_query, sphere_data);
-            if sphere_obs.is_dynamic {
-                dyn_obstacle_handles.push((sphere_handle.0, sphere_handle.1.data().name.clone()));
-            }
+            let sphere_pos = Isometry3::from_parts(sphere_ts, UnitQuaternion::identity());
+            obstacles.push(CollisionObstacle::new(sphere_obs.name.clone(), sphere, sphere_pos, sphere_obs.is_dynamic));
         }
 
-        for i in 0..pcd_obstacles.len() {
-            let pcd_obs = &pcd_obstacles[i];
-            // let mut shapes: Vec<(Isometry3<f64>, ShapeHandle<f64>)> = Vec::new();
-            // for sphere_obs in &pcd_obs.points {
-            //     let sphere = ShapeHandle::new(Ball::new(sphere_obs.radius));
-            //     let sphere_ts = Translation3::new(sphere_obs.tx, sphere_obs.ty, sphere_obs.tz);
-            //     let sphere_rot = UnitQuaternion::identity();
-            //     let sphere_pos = Isometry3::from_parts(sphere_ts, sphere_rot);
-            //     shapes.push((sphere_pos, sphere));
-            // }
-            let mut points: Vec<Point3<f64>> = Vec::new();
-            for sphere_obs in &pcd_obs.points {
-                points.push(Point3::new(sphere_obs.tx, sphere_obs.ty, sphere_obs.tz));
-            }
-            // let pcd = ShapeHandle::new(Compound::new(shapes));
-            let pcd = ShapeHandle::new(ConvexHull::try_from_points(&points).unwrap());
+        for pcd_obs in &pcd_obstacles {
+            let points: Vec<Point3<f64>> = pcd_obs.points.iter().map(|p| Point3::new(p[0], p[1], p[2])).collect();
+            let pcd = SharedShape::convex_hull(&points)
+                .unwrap_or_else(|| panic!("Failed to compute the convex hull of point cloud obstacle {}", pcd_obs.name));
             let pcd_ts = Translation3::new(pcd_obs.tx, pcd_obs.ty, pcd_obs.tz);
             let pcd_rot = UnitQuaternion::from_euler_angles(pcd_obs.rx, pcd_obs.ry, pcd_obs.rz);
             let pcd_pos = Isometry3::from_parts(pcd_ts, pcd_rot);
-            let pcd_data = CollisionObjectData::new(pcd_obs.name.clone(), LinkData::new(false, -1));
-            let pcd_handle = world.add(pcd_pos, pcd, others_groups, proximity_query, pcd_data);
-            if pcd_obs.is_dynamic {
-                dyn_obstacle_handles.push((pcd_handle.0, pcd_handle.1.data().name.clone()));
-            }
+            obstacles.push(CollisionObstacle::new(pcd_obs.name.clone(), pcd, pcd_pos, pcd_obs.is_dynamic));
         }
-        
-        return Self{world, link_radius, link_handles, dyn_obstacle_handles, active_pairs, active_obstacles};
+
+        println!("Loaded {} environment obstacles", obstacles.len());
+
+        Self{obstacles, link_radius, proximity_distance: 20.0 * link_radius}
     }
 
-    pub fn update_links(
-        &mut self,
-        frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>,
-    ) {
-        for arm_idx in 0..frames.len() {
-            let last_elem = frames[arm_idx].0.len() - 1;
-            for i in 0..last_elem {
-                let start_pt = Point3::from(frames[arm_idx].0[i]);
-                let end_pt = Point3::from(frames[arm_idx].0[i + 1]);
-                let segment = ShapeHandle::new(Segment::new(start_pt, end_pt));
-                let link = self.world.objects.get_mut(self.link_handles[arm_idx][i]).unwrap();
-                link.set_shape(segment);
+    // Distance between the surface of the obstacle and the centerline of the link segment (start, end)
+    pub fn link_distance(&self, obstacle: &CollisionObstacle, start: &Vector3<f64>, end: &Vector3<f64>) -> f64 {
+        let segment = Segment::new(Point3::from(*start), Point3::from(*end));
+        query::distance(&obstacle.position, obstacle.shape.as_ref(), &Isometry3::identity(), &segment).unwrap()
+    }
+
+    // Distance between the obstacle and the link segment (start, end) for endpoints over any Real scalar.
+    // The distance is linearized at the closest points, which is exact to first order, so dual numbers
+    // carry its derivatives. Returns None if the obstacle is farther than max_dist.
+    pub fn link_distance_generic<T: Real>(&self, obstacle: &CollisionObstacle, start: &Vector3<T>, end: &Vector3<T>, max_dist: f64) -> Option<T> {
+        let start_val = vec3_value(start);
+        let end_val = vec3_value(end);
+        let segment = Segment::new(Point3::from(start_val), Point3::from(end_val));
+        match query::closest_points(&obstacle.position, obstacle.shape.as_ref(), &Isometry3::identity(), &segment, max_dist).unwrap() {
+            ClosestPoints::Intersecting => Some(T::zero()),
+            ClosestPoints::WithinMargin(mut obstacle_pt, link_pt) => {
+                let dir = end_val - start_val;
+                let mut t = ((link_pt.coords - start_val).dot(&dir) / dir.norm_squared()).clamp(0.0, 1.0);
+                // refine the GJK points by alternating exact projections, see self_collision
+                for _ in 0..3 {
+                    obstacle_pt = project_point(&obstacle.position, &obstacle.shape, &Point3::from(start_val + dir * t));
+                    t = ((obstacle_pt.coords - start_val).dot(&dir) / dir.norm_squared()).clamp(0.0, 1.0);
+                }
+                let link_pt = start + (end - start) * T::from_f64(t);
+                Some(vec3_norm(&(link_pt - vec3_from_f64(&obstacle_pt.coords))))
+            },
+            ClosestPoints::Disjoint => None
+        }
+    }
+
+    // Returns true if any link of the arm, modeled as a capsule of link_radius, intersects an obstacle
+    pub fn check_collision(&self, arm_frames: &(Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>)) -> bool {
+        let positions = &arm_frames.0;
+        for obstacle in &self.obstacles {
+            for i in 0..positions.len() - 1 {
+                if self.link_distance(obstacle, &positions[i], &positions[i + 1]) < self.link_radius {
+                    return true;
+                }
             }
         }
+        false
     }
 
     pub fn update_dynamic_obstacle(
@@ -170,10 +122,9 @@ impl RelaxedIKEnvCollision {
         name: &str,
         position: Isometry3<f64>,
     ) {
-        for (handle, id) in &self.dyn_obstacle_handles {
-            if id == name {
-                let co = self.world.objects.get_mut(*handle).unwrap();
-                co.set_position(position);
+        for obstacle in self.obstacles.iter_mut() {
+            if obstacle.is_dynamic && obstacle.name == name {
+                obstacle.position = position;
                 break;
             }
         }
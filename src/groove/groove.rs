@@ -1,19 +1,89 @@
 use crate::groove::gradient::{ForwardFiniteDiff, CentralFiniteDiff, GradientFinder, ForwardFiniteDiffImmutable, CentralFiniteDiffImmutable, GradientFinderImmutable};
 use crate::groove::vars::{RelaxedIKVars};
-use optimization_engine::{constraints::*, panoc::*, *};
+use optimization_engine::{constraints::*, panoc::*, core::ExitStatus, *};
 use crate::groove::objective_master::ObjectiveMaster;
+use std::time::{Duration, Instant};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    Converged,
+    MaxIterations,
+    OutOfTime,
+    // the solver stopped making progress short of the tolerance, e.g. against the joint limits
+    Stalled,
+    // the cost, its gradient or the solution was not finite
+    NotFinite,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SolveResult {
+    pub iterations: usize,
+    pub converged: bool,
+    // cost at the returned solution, NaN if the solver failed
+    pub cost: f64,
+    pub exit_reason: ExitReason,
+    pub solve_time: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolverOptions {
+    // PANOC stops when the fixed-point residual is below tolerance. It also replaces the
+    // tolerance the cache is created with, so there is no separate cache tolerance.
+    pub tolerance: f64,
+    pub lbfgs_memory: usize,
+    // iteration limits of RelaxedIK::solve with and without the velocity constraint
+    pub max_iter_relaxed_ik: usize,
+    pub max_iter_standard_ik: usize,
+    // wall-clock limit of one optimize call, after which the current iterate is returned
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self{tolerance: 0.0001, lbfgs_memory: 10, max_iter_relaxed_ik: 100, max_iter_standard_ik: 1000, time_budget: None}
+    }
+}
+
+// An optimizer that RelaxedIK can run on an objective master. x holds the initial guess and is
+// overwritten with the solution.
+pub trait IKSolver {
+    fn optimize(&mut self, x: &mut [f64], v: &RelaxedIKVars, om: &ObjectiveMaster, max_iter: usize) -> SolveResult;
+    fn options(&self) -> SolverOptions;
+    fn set_options(&mut self, options: SolverOptions);
+}
+
+// PANOC from optimization_engine
 pub struct OptimizationEngineOpen {
     dim: usize,
-    cache: PANOCCache
+    cache: PANOCCache,
+    options: SolverOptions
 }
 impl OptimizationEngineOpen {
     pub fn new(dim: usize) -> Self {
-        let mut cache = PANOCCache::new(dim, 1e-14, 10);
-        OptimizationEngineOpen { dim, cache }
+        Self::with_options(dim, SolverOptions::default())
+    }
+
+    pub fn with_options(dim: usize, options: SolverOptions) -> Self {
+        let cache = PANOCCache::new(dim, options.tolerance, options.lbfgs_memory);
+        OptimizationEngineOpen { dim, cache, options }
+    }
+}
+
+impl IKSolver for OptimizationEngineOpen {
+    fn options(&self) -> SolverOptions {
+        self.options
+    }
+
+    // The cache is only reallocated when the L-BFGS memory changes
+    fn set_options(&mut self, options: SolverOptions) {
+        if options.lbfgs_memory != self.options.lbfgs_memory {
+            self.cache = PANOCCache::new(self.dim, options.tolerance, options.lbfgs_memory);
+        }
+        self.options = options;
     }
 
-    pub fn optimize(&mut self, x: &mut [f64], v: &RelaxedIKVars, om: &ObjectiveMaster, max_iter: usize) {
+    fn optimize(&mut self, x: &mut [f64], v: &RelaxedIKVars, om: &ObjectiveMaster, max_iter: usize) -> SolveResult {
+        let start = Instant::now();
         let df = |u: &[f64], grad: &mut [f64]| -> Result<(), SolverError> {
             let (my_obj, my_grad) = om.gradient(u, v);
             for i in 0..my_grad.len() {
@@ -28,17 +98,33 @@ impl OptimizationEngineOpen {
         };
 
         // let bounds = NoConstraints::new();
-        let bounds = Rectangle::new(Option::from(v.robot.lower_joint_limits.as_slice()), Option::from(v.robot.upper_joint_limits.as_slice()));
+        let (lower_limits, upper_limits) = (v.robot.lower_joint_limits(), v.robot.upper_joint_limits());
+        let bounds = Rectangle::new(Option::from(lower_limits.as_slice()), Option::from(upper_limits.as_slice()));
 
         /* PROBLEM STATEMENT */
         let problem = Problem::new(&bounds, df, f);
-        let mut panoc = PANOCOptimizer::new(problem, &mut self.cache).with_max_iter(max_iter).with_tolerance(0.0001);
+        let mut panoc = PANOCOptimizer::new(problem, &mut self.cache).with_max_iter(max_iter).with_tolerance(self.options.tolerance);
         // let mut panoc = PANOCOptimizer::new(problem, &mut self.cache);
+        if let Some(time_budget) = self.options.time_budget {
+            panoc = panoc.with_max_duration(time_budget);
+        }
 
         // Invoke the solver
         let status = panoc.solve(x);
 
         // println!("Panoc status: {:#?}", status);
         // println!("Panoc solution: {:#?}", x);
+        match status {
+            Ok(status) => {
+                let exit_reason = match status.exit_status() {
+                    ExitStatus::Converged => ExitReason::Converged,
+                    ExitStatus::NotConvergedIterations => ExitReason::MaxIterations,
+                    ExitStatus::NotConvergedOutOfTime => ExitReason::OutOfTime
+                };
+                SolveResult{iterations: status.iterations(), converged: status.has_converged(), cost: status.cost_value(),
+                    exit_reason, solve_time: start.elapsed()}
+            },
+            Err(_) => SolveResult{iterations: 0, converged: false, cost: f64::NAN, exit_reason: ExitReason::NotFinite, solve_time: start.elapsed()}
+        }
     }
 }
@@ -1,6 +1,8 @@
 pub mod objective;
 pub mod groove;
+pub mod solvers;
 pub mod vars;
 pub mod gradient;
 pub mod objective_master;
-// pub mod env_collision;
\ No newline at end of file
+pub mod env_collision;
+pub mod self_collision;
\ No newline at end of file
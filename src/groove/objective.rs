@@ -1,37 +1,50 @@
 use crate::groove::{vars};
 use crate::utils_rust::transformations::{*};
-use nalgebra::geometry::{Translation3, UnitQuaternion, Quaternion};
+use crate::utils_rust::autodiff::{Real, Dual, Quat, DUAL_WIDTH, seed_duals, vec3_from_f64, vec3_norm};
+use nalgebra::geometry::UnitQuaternion;
 use std::cmp;
 use crate::groove::vars::RelaxedIKVars;
-use nalgebra::{Vector3, Isometry3, Point3};
+use nalgebra::{Vector3, Isometry3, DMatrix};
+use crate::spacetime::robot_kinematics::RobotKinematics;
 use std::ops::Deref;
 use time::PreciseTime;
-use parry3d_f64::{shape, query};
 
-pub fn quadratic_loss(x_val: f64, t: f64, g: i32) -> f64 {
+pub fn quadratic_loss<T: Real>(x_val: T, t: f64, g: i32) -> T {
     (x_val - t).powi(g)
 }
 
-pub fn groove_loss(x_val: f64, t: f64, d: i32, c: f64, f: f64, g: i32) -> f64 {
-    -( (-(x_val - t).powi(d)) / (2.0 * c.powi(2) ) ).exp() + f * (x_val - t).powi(g)
+pub fn quadratic_loss_derivative(x_val: f64, t: f64, g: i32) -> f64 {
+    g as f64 * (x_val - t).powi(g - 1)
+}
+
+pub fn groove_loss<T: Real>(x_val: T, t: f64, d: i32, c: f64, f: f64, g: i32) -> T {
+    -( (-(x_val - t).powi(d)) / (2.0 * c.powi(2) ) ).exp() + (x_val - t).powi(g) * f
 }
 
 pub fn groove_loss_derivative(x_val: f64, t: f64, d: i32, c: f64, f: f64, g: i32) -> f64 {
-    -( (-(x_val - t).powi(d)) / (2.0 * c.powi(2) ) ).exp() *  ((-d as f64 * (x_val - t)) /  (2.0 * c.powi(2))) + g as f64 * f * (x_val - t)
+    -( (-(x_val - t).powi(d)) / (2.0 * c.powi(2) ) ).exp() *  ((-d as f64 * (x_val - t).powi(d - 1)) /  (2.0 * c.powi(2))) + g as f64 * f * (x_val - t).powi(g - 1)
 }
 
-pub fn swamp_groove_loss(x_val: f64, g:f64, l_bound: f64, u_bound: f64, c : f64, f1: f64, f2: f64, f3:f64, p1:i32) -> f64 {
-    let x = (2.0 * x_val - l_bound - u_bound) / (u_bound - l_bound);
+pub fn swamp_groove_loss<T: Real>(x_val: T, g:f64, l_bound: f64, u_bound: f64, c : f64, f1: f64, f2: f64, f3:f64, p1:i32) -> T {
+    let x = (x_val * 2.0 - l_bound - u_bound) / (u_bound - l_bound);
     let b = (-1.0 / (0.05 as f64).ln()).powf(1.0 / p1 as f64);
-    - f1 * ( (-(x_val-g).powi(2)) / (2.0 * c.powi(2)) ).exp() 
-    + f2 * (x_val-g).powi(2) 
-    + f3 * (1.0 - (-(x/b).powi(p1)).exp()) 
+    - ( (-(x_val-g).powi(2)) / (2.0 * c.powi(2)) ).exp() * f1
+    + (x_val-g).powi(2) * f2
+    - ((-(x/b).powi(p1)).exp() - 1.0) * f3
 }
 
-pub fn swamp_loss(x_val: f64, l_bound: f64, u_bound: f64, f1: f64, f2: f64, p1:i32) -> f64 {
+pub fn swamp_loss<T: Real>(x_val: T, l_bound: f64, u_bound: f64, f1: f64, f2: f64, p1:i32) -> T {
+    let x = (x_val * 2.0 - l_bound - u_bound) / (u_bound - l_bound);
+    let b = (-1.0 / (0.05 as f64).ln()).powf(1.0 / p1 as f64);
+    -(x.powi(2) * f2 + f1) *  ((- (x/b).powi(p1)).exp() - 1.0) - 1.0
+}
+
+pub fn swamp_loss_derivative(x_val: f64, l_bound: f64, u_bound: f64, f1: f64, f2: f64, p1:i32) -> f64 {
     let x = (2.0 * x_val - l_bound - u_bound) / (u_bound - l_bound);
     let b = (-1.0 / (0.05 as f64).ln()).powf(1.0 / p1 as f64);
-    (f1 + f2 * x.powi(2)) *  (1.0 - (- (x/b).powi(p1)).exp()) - 1.0
+    let e = (- (x/b).powi(p1)).exp();
+    let dx = 2.0 / (u_bound - l_bound);
+    (2.0 * f2 * x * (1.0 - e) + (f1 + f2 * x.powi(2)) * e * p1 as f64 * (x/b).powi(p1 - 1) / b) * dx
 }
 
 pub fn swamp_groove_loss_derivative(x_val: f64, g:f64, l_bound: f64, u_bound: f64, c : f64, f1: f64, f2: f64, f3:f64, p1:i32) -> f64 {
@@ -41,306 +54,578 @@ pub fn swamp_groove_loss_derivative(x_val: f64, g:f64, l_bound: f64, u_bound: f6
     let x = (2.0 * x_val - l_bound - u_bound) / (u_bound - l_bound);
     let b = (-1.0 / (0.05 as f64).ln()).powf(1.0 / p1 as f64);
 
-    - f1 * ( (-x_val.powi(2)) / (2.0 * c.powi(2) ) ).exp() *  ((-2.0 * x_val) /  (2.0 * c.powi(2))) 
-    + 2.0 * f2 * x_val 
+    - f1 * ( (-(x_val-g).powi(2)) / (2.0 * c.powi(2) ) ).exp() *  ((-2.0 * (x_val-g)) /  (2.0 * c.powi(2))) 
+    + 2.0 * f2 * (x_val-g) 
     + f3 / (2.0 * x_val - l_bound - u_bound) * ( 2.0 * (x/b).powi(p1) * p1 as f64 * (- (x/b).powi(p1)).exp()) 
 }
 
+// Shape of groove_loss around the target value t of an objective
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrooveParams {
+    pub d: i32,
+    pub c: f64,
+    pub f: f64,
+    pub g: i32
+}
+
+impl GrooveParams {
+    // used by the end effector and smoothness objectives
+    pub const MOTION: Self = Self{d: 2, c: 0.1, f: 10.0, g: 2};
+    pub const COLLISION: Self = Self{d: 2, c: 3.5, f: 0.00005, g: 4};
+    pub const MANIPULABILITY: Self = Self{d: 2, c: 0.5, f: 0.1, g: 2};
+
+    pub fn loss<T: Real>(&self, x_val: T, t: f64) -> T {
+        groove_loss(x_val, t, self.d, self.c, self.f, self.g)
+    }
+
+    pub fn loss_derivative(&self, x_val: f64, t: f64) -> f64 {
+        groove_loss_derivative(x_val, t, self.d, self.c, self.f, self.g)
+    }
+}
+
+// Shape of swamp_loss, which is flat inside [l_bound, u_bound] and steep outside
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwampParams {
+    pub f1: f64,
+    pub f2: f64,
+    pub p1: i32
+}
+
+impl SwampParams {
+    pub const ROTATION: Self = Self{f1: 100.0, f2: 0.1, p1: 20};
+    pub const JOINT_LIMITS: Self = Self{f1: 10.0, f2: 10.0, p1: 20};
+    // used by the look-at distance band and view cone
+    pub const BAND: Self = Self{f1: 10.0, f2: 10.0, p1: 20};
+
+    pub fn loss<T: Real>(&self, x_val: T, l_bound: f64, u_bound: f64) -> T {
+        swamp_loss(x_val, l_bound, u_bound, self.f1, self.f2, self.p1)
+    }
+
+    pub fn loss_derivative(&self, x_val: f64, l_bound: f64, u_bound: f64) -> f64 {
+        swamp_loss_derivative(x_val, l_bound, u_bound, self.f1, self.f2, self.p1)
+    }
+}
+
+// Value and gradient of groove_loss(|r|) for residuals r that are linear in x with unit slope
+fn norm_loss_gradient(residuals: &[f64], groove: &GrooveParams) -> (f64, Vec<f64>) {
+    let x_val = residuals.iter().map(|r| r.powi(2)).sum::<f64>().sqrt();
+    let dloss = groove.loss_derivative(x_val, 0.0);
+    let grad = if x_val > 0.0 {
+        residuals.iter().map(|r| dloss * r / x_val).collect()
+    } else {
+        vec![0.0; residuals.len()]
+    };
+    (groove.loss(x_val, 0.0), grad)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GradientType {
+    // the objective implements gradient
+    Analytic,
+    // forward differences of call, computed by the ObjectiveMaster
+    FiniteDiff,
+    // call_dual evaluated with dual numbers, computed by the ObjectiveMaster
+    AutoDiff
+}
+
+// What an objective reads from the robot state besides the joint values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KinematicNeeds {
+    pub ee_poses: bool,
+    pub frames: bool,
+    pub jacobians: bool
+}
+
+impl KinematicNeeds {
+    pub const JOINTS: Self = Self{ee_poses: false, frames: false, jacobians: false};
+    pub const EE_POSES: Self = Self{ee_poses: true, frames: false, jacobians: false};
+    pub const FRAMES: Self = Self{ee_poses: true, frames: true, jacobians: false};
+
+    pub fn with_jacobians(self) -> Self {
+        Self{jacobians: true, ..self}
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self{ee_poses: self.ee_poses || other.ee_poses, frames: self.frames || other.frames, jacobians: self.jacobians || other.jacobians}
+    }
+}
+
+// Robot state at one configuration, shared by all objectives of an evaluation. Fields that were
+// not requested are left empty.
+pub struct KinematicState {
+    pub ee_poses: Vec<(Vector3<f64>, UnitQuaternion<f64>)>,
+    pub frames: Vec<(Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>)>,
+    // end effector jacobian of each chain over all dofs
+    pub jacobians: Vec<DMatrix<f64>>
+}
+
+impl KinematicState {
+    pub fn new(robot: &RobotKinematics, x: &[f64], needs: KinematicNeeds) -> Self {
+        let mut ee_poses = Vec::new();
+        let mut frames = Vec::new();
+        let mut jacobians = Vec::new();
+        if needs.frames || needs.jacobians {
+            frames = robot.get_frames_immutable(x);
+            ee_poses = frames.iter().map(|(positions, quats)| (positions[positions.len() - 1], quats[quats.len() - 1])).collect();
+            if needs.jacobians {
+                for i in 0..robot.num_chains {
+                    jacobians.push(robot.get_chain_jacobian_from_frames(&frames[i], i, x.len()));
+                }
+            }
+        } else if needs.ee_poses {
+            ee_poses = robot.get_ee_pos_and_quat_immutable(x);
+        }
+        Self{ee_poses, frames, jacobians}
+    }
+}
+
 pub trait ObjectiveTrait {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64;
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64;
-    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> (f64, Vec<f64>) {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64;
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
         let mut grad: Vec<f64> = Vec::new();
-        let f_0 = self.call(x, v, frames);
-        
+        let f_0 = self.call(x, v, state);
+
         for i in 0..x.len() {
             let mut x_h = x.to_vec();
             x_h[i] += 0.000000001;
-            let frames_h = v.robot.get_frames_immutable(x_h.as_slice());
-            let f_h = self.call(x_h.as_slice(), v, &frames_h);
+            let state_h = KinematicState::new(&v.robot, x_h.as_slice(), self.needs());
+            let f_h = self.call(x_h.as_slice(), v, &state_h);
             grad.push( (-f_0 + f_h) / 0.000000001);
         }
 
         (f_0, grad)
     }
-    fn gradient_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> (f64, Vec<f64>) {
-        let mut grad: Vec<f64> = Vec::new();
-        let f_0 = self.call_lite(x, v, ee_poses);
-
-        for i in 0..x.len() {
-            let mut x_h = x.to_vec();
-            x_h[i] += 0.0000001;
-            let ee_poses_h = v.robot.get_ee_pos_and_quat_immutable(x_h.as_slice());
-            let f_h = self.call_lite(x_h.as_slice(), v, &ee_poses_h);
-            grad.push( (-f_0 + f_h) / 0.0000001);
-        }
+    // Same as call, evaluated with dual numbers. Objectives that implement it and return
+    // GradientType::AutoDiff are differentiated automatically by the ObjectiveMaster.
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        panic!("This objective can not be evaluated with dual numbers")
+    }
+    fn gradient_type(&self) -> GradientType {GradientType::FiniteDiff}
+    // state read by call
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::FRAMES}
+    // state read by gradient, only used for GradientType::Analytic
+    fn gradient_needs(&self) -> KinematicNeeds {self.needs()}
+}
 
-        (f_0, grad)
+// Gradient of a single objective using forward-mode automatic differentiation, one forward
+// kinematics pass per DUAL_WIDTH joints
+pub fn autodiff_gradient(objective: &dyn ObjectiveTrait, x: &[f64], v: &vars::RelaxedIKVars) -> (f64, Vec<f64>) {
+    let mut grad = vec![0.0; x.len()];
+    let mut f_0 = 0.0;
+    for offset in (0..x.len().max(1)).step_by(DUAL_WIDTH) {
+        let x_dual = seed_duals(x, offset);
+        let frames = v.robot.get_frames_generic(&x_dual);
+        let out = objective.call_dual(&x_dual, v, &frames);
+        f_0 = out.re;
+        let end = x.len().min(offset + DUAL_WIDTH);
+        grad[offset..end].copy_from_slice(&out.eps[..end - offset]);
     }
-    fn gradient_type(&self) -> usize {return 1}  // manual diff = 0, finite diff = 1
+    (f_0, grad)
 }
 
 
 pub struct MatchEEPosiDoF {
     pub arm_idx: usize,
-    pub axis: usize
+    pub axis: usize,
+    pub groove: GrooveParams
 }
 impl MatchEEPosiDoF {
-    pub fn new(arm_idx: usize, axis: usize) -> Self {Self{arm_idx, axis}}
-}
-impl ObjectiveTrait for MatchEEPosiDoF {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let last_elem = frames[self.arm_idx].0.len() - 1;
-        let goal_quat = v.goal_quats[self.arm_idx];
+    pub fn new(arm_idx: usize, axis: usize) -> Self {Self{arm_idx, axis, groove: GrooveParams::MOTION}}
+
+    fn loss<T: Real>(&self, ee_position: &Vector3<T>, v: &vars::RelaxedIKVars) -> T {
+        let goal_quat: Quat<T> = Quat::from_unit_quaternion(&v.goal_quats[self.arm_idx]);
         // E_{gc} = R_{gw} * T_{gw} * T_{wc} * R_{wc}, R_{wc} won't matter since we are only interested in the translation
         // so  we get: T_{gc} = R_{gw} * T_{gw} * T_{wc}
-        let T_gw_T_wc =  nalgebra::Vector3::new(    frames[self.arm_idx].0[last_elem].x - v.goal_positions[self.arm_idx].x, 
-                                                    frames[self.arm_idx].0[last_elem].y - v.goal_positions[self.arm_idx].y, 
-                                                    frames[self.arm_idx].0[last_elem].z - v.goal_positions[self.arm_idx].z );
+        let T_gw_T_wc = ee_position - vec3_from_f64(&v.goal_positions[self.arm_idx]);
 
-        let T_gc = goal_quat.inverse() * T_gw_T_wc;
+        let T_gc = goal_quat.inverse().rotate(&T_gw_T_wc);
  
-        let dist: f64 = T_gc[self.axis];
+        let dist: T = T_gc[self.axis];
 
         let bound =  v.tolerances[self.arm_idx][self.axis];
 
         if (bound <= 1e-2) {
-            groove_loss(dist, 0., 2, 0.1, 10.0, 2)
+            self.groove.loss(dist, 0.)
         } else {
             swamp_groove_loss(dist, 0.0, -bound, bound, bound*2.0, 1.0, 0.01, 100.0, 20) 
         }
     }
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let x_val = ( ee_poses[self.arm_idx].0 - v.goal_positions[self.arm_idx] ).norm();
-        groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
+}
+impl ObjectiveTrait for MatchEEPosiDoF {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(&state.ee_poses[self.arm_idx].0, v)
+    }
+
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        let last_elem = frames[self.arm_idx].0.len() - 1;
+        self.loss(&frames[self.arm_idx].0[last_elem], v)
     }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let goal_quat = v.goal_quats[self.arm_idx];
+        let dist = (goal_quat.inverse() * (state.ee_poses[self.arm_idx].0 - v.goal_positions[self.arm_idx]))[self.axis];
+        let bound =  v.tolerances[self.arm_idx][self.axis];
+
+        let (f_0, dloss) = if bound <= 1e-2 {
+            (self.groove.loss(dist, 0.), self.groove.loss_derivative(dist, 0.))
+        } else {
+            (swamp_groove_loss(dist, 0.0, -bound, bound, bound*2.0, 1.0, 0.01, 100.0, 20),
+                swamp_groove_loss_derivative(dist, 0.0, -bound, bound, bound*2.0, 1.0, 0.01, 100.0, 20))
+        };
+
+        // d(dist)/dx is the goal-frame linear velocity along the axis
+        let jacobian = &state.jacobians[self.arm_idx];
+        let mut grad = vec![0.0; x.len()];
+        for i in 0..x.len() {
+            let linear = Vector3::new(jacobian[(0, i)], jacobian[(1, i)], jacobian[(2, i)]);
+            grad[i] = dloss * (goal_quat.inverse() * linear)[self.axis];
+        }
+        (f_0, grad)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
 }
 
 pub struct MatchEERotaDoF {
     pub arm_idx: usize,
-    pub axis: usize
+    pub axis: usize,
+    pub groove: GrooveParams,
+    // used when the tolerance covers all rotations about the axis
+    pub swamp: SwampParams
 }
 impl MatchEERotaDoF {
-    pub fn new(arm_idx: usize, axis: usize) -> Self {Self{arm_idx, axis}}
-}
-impl ObjectiveTrait for MatchEERotaDoF {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let last_elem = frames[self.arm_idx].1.len() - 1;
-        let ee_quat = frames[self.arm_idx].1[last_elem];
-        let goal_quat = v.goal_quats[self.arm_idx];
-        let rotation = goal_quat.inverse()*ee_quat;
+    pub fn new(arm_idx: usize, axis: usize) -> Self {Self{arm_idx, axis, groove: GrooveParams::MOTION, swamp: SwampParams::ROTATION}}
 
+    fn loss<T: Real>(&self, ee_quat: &Quat<T>, v: &vars::RelaxedIKVars) -> T {
+        let goal_quat: Quat<T> = Quat::from_unit_quaternion(&v.goal_quats[self.arm_idx]);
+        let rotation = goal_quat.inverse().mul(ee_quat);
 
-        let euler = rotation.euler_angles(); 
         let scaled_axis = rotation.scaled_axis();
 
-        // println!("axisAngle: {:?} {:?}", euler, axisAngle);
-        
-        let mut angle: f64 = 0.0;
-        angle += scaled_axis[self.axis].abs();
+        let angle: T = scaled_axis[self.axis].abs();
 
         let bound =  v.tolerances[self.arm_idx][self.axis + 3];
 
         if (bound <= 1e-2) {
-            groove_loss(angle, 0., 2, 0.1, 10.0, 2)
+            self.groove.loss(angle, 0.)
         } else {
             if bound >= 3.14159260 {
-                swamp_loss(angle, -bound, bound, 100.0, 0.1, 20) 
+                self.swamp.loss(angle, -bound, bound)
             } else {
                 swamp_groove_loss(angle, 0.0, -bound, bound, bound*2.0, 1.0, 0.01, 100.0, 20) 
                 // swamp_groove_loss(angle, 0.0, -bound, bound, 10.0, 1.0, 0.01, 100.0, 20)
             }
         }
     }
+}
+impl ObjectiveTrait for MatchEERotaDoF {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(&Quat::from_unit_quaternion(&state.ee_poses[self.arm_idx].1), v)
+    }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let x_val = ( ee_poses[self.arm_idx].0 - v.goal_positions[self.arm_idx] ).norm();
-        groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        let last_elem = frames[self.arm_idx].1.len() - 1;
+        self.loss(&frames[self.arm_idx].1[last_elem], v)
     }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let goal_quat = v.goal_quats[self.arm_idx];
+        let scaled_axis = (goal_quat.inverse() * state.ee_poses[self.arm_idx].1).scaled_axis();
+        let angle = scaled_axis[self.axis].abs();
+        let bound =  v.tolerances[self.arm_idx][self.axis + 3];
+
+        let (f_0, dloss) = if bound <= 1e-2 {
+            (self.groove.loss(angle, 0.), self.groove.loss_derivative(angle, 0.))
+        } else if bound >= 3.14159260 {
+            (self.swamp.loss(angle, -bound, bound), self.swamp.loss_derivative(angle, -bound, bound))
+        } else {
+            (swamp_groove_loss(angle, 0.0, -bound, bound, bound*2.0, 1.0, 0.01, 100.0, 20),
+                swamp_groove_loss_derivative(angle, 0.0, -bound, bound, bound*2.0, 1.0, 0.01, 100.0, 20))
+        };
+
+        // the rotation goal^-1 * ee is perturbed on the left by the angular velocity in the goal frame,
+        // which maps to the rate of the rotation vector through the inverse left jacobian of SO(3)
+        let jl_inv = so3_left_jacobian_inverse(&scaled_axis);
+        let sign = if scaled_axis[self.axis] >= 0.0 { 1.0 } else { -1.0 };
+        let jacobian = &state.jacobians[self.arm_idx];
+        let mut grad = vec![0.0; x.len()];
+        for i in 0..x.len() {
+            let angular = Vector3::new(jacobian[(3, i)], jacobian[(4, i)], jacobian[(5, i)]);
+            grad[i] = dloss * sign * (jl_inv * (goal_quat.inverse() * angular))[self.axis];
+        }
+        (f_0, grad)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
 }
 
 pub struct SelfCollision {
-    pub arm_idx: usize,
-    pub first_link: usize,
-    pub second_link: usize
+    pub groove: GrooveParams
 }
 impl SelfCollision {
-    pub fn new(arm_idx: usize, first_link: usize, second_link: usize) -> Self {Self{arm_idx, first_link, second_link}}
+    pub fn new() -> Self {Self{groove: GrooveParams::COLLISION}}
+
+    fn loss<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars, frames: &[(Vec<nalgebra::Vector3<T>>, Vec<Quat<T>>)]) -> T {
+        if x.iter().any(|x_i| x_i.value().is_nan()) {
+            return T::from_f64(10.0)
+        }
+
+        let mut x_val = T::zero();
+        let penalty_cutoff: f64 = v.self_collision.link_radius;
+        let a = penalty_cutoff.powi(2);
+        for dis in v.self_collision.proximity_distances_generic(frames) {
+            x_val += T::from_f64(a) / dis.max(T::from_f64(1e-3)).powi(2);
+        }
+
+        self.groove.loss(x_val, 0.)
+    }
 }
 impl ObjectiveTrait for SelfCollision {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        for i in 0..x.len() {
-            if (x[i].is_nan()) {
-                return 10.0
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        let frames: Vec<(Vec<nalgebra::Vector3<f64>>, Vec<Quat<f64>>)> = state.frames.iter()
+            .map(|(positions, quats)| (positions.clone(), quats.iter().map(Quat::from_unit_quaternion).collect())).collect();
+        self.loss(x, v, &frames)
+    }
+
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.loss(x, v, frames)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::AutoDiff}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::FRAMES}
+}
+
+
+pub struct EnvCollision {
+    pub arm_idx: usize,
+    pub groove: GrooveParams
+}
+impl EnvCollision {
+    pub fn new(arm_idx: usize) -> Self {Self{arm_idx, groove: GrooveParams::COLLISION}}
+
+    fn loss<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars, positions: &[nalgebra::Vector3<T>]) -> T {
+        if x.iter().any(|x_i| x_i.value().is_nan()) {
+            return T::from_f64(10.0)
+        }
+
+        let mut x_val = T::zero();
+        let link_radius = v.env_collision.link_radius;
+        let penalty_cutoff: f64 = link_radius * 2.0;
+        let a = penalty_cutoff.powi(2);
+        let last_elem = positions.len() - 1;
+        for obstacle in &v.env_collision.obstacles {
+            let mut sum = T::zero();
+            for i in 0..last_elem {
+                let start_pt = positions[i];
+                let end_pt = positions[i + 1];
+                // zero-length links come from joints sharing the same origin
+                if vec3_norm(&(end_pt - start_pt)).value() < 1e-6 {
+                    continue;
+                }
+                let max_dist = v.env_collision.proximity_distance + link_radius;
+                let centerline_dis = match v.env_collision.link_distance_generic(obstacle, &start_pt, &end_pt, max_dist) {
+                    Some(dis) => dis,
+                    None => continue
+                };
+                // the link centerline touching the obstacle would give an infinite penalty
+                sum += T::from_f64(a) / centerline_dis.max(T::from_f64(1e-3)).powi(2);
             }
+            x_val += sum;
         }
-       
-        let mut x_val: f64 = 0.0;
-        let link_radius = 0.05;
-
-        let start_pt_1 = Point3::from(frames[self.arm_idx].0[self.first_link]);
-        let end_pt_1 = Point3::from(frames[self.arm_idx].0[self.first_link+1]);
-        let segment_1 = shape::Segment::new(start_pt_1, end_pt_1);
-
-        let mut start_pt_2 = Point3::from(frames[self.arm_idx].0[self.second_link]);
-        let mut end_pt_2 = Point3::from(frames[self.arm_idx].0[self.second_link+1]);
-
-        let segment_2 = shape::Segment::new(start_pt_2, end_pt_2);
-
-        let segment_pos = nalgebra::one();
-        // println!("start_pt_1:{} end_pt_1:{}  start_pt_2:{} end_pt_2:{} x: {:?}", start_pt_1, end_pt_1, start_pt_2, end_pt_2, x);
-
-        let dis = query::distance(&segment_pos, &segment_1, &segment_pos, &segment_2).unwrap() - 0.05;
-       
-        swamp_loss(dis, 0.02, 1.5, 60.0, 0.0001, 30)
-    }
-
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let x_val = 1.0; // placeholder
-        groove_loss(x_val, 0., 2, 2.1, 0.0002, 4)
-    }
-}
-
-
-// pub struct EnvCollision {
-//     pub arm_idx: usize
-// }
-// impl EnvCollision {
-//     pub fn new(arm_idx: usize) -> Self {Self{arm_idx}}
-// }
-// impl ObjectiveTrait for EnvCollision {
-//     fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-//         // let start = PreciseTime::now();\
-        
-//         for i in 0..x.len() {
-//             if (x[i].is_nan()) {
-//                 return 10.0
-//             }
-//         }
-        
-//         let mut x_val: f64 = 0.0;
-//         let link_radius = v.env_collision.link_radius;
-//         let penalty_cutoff: f64 = link_radius * 2.0;
-//         let a = penalty_cutoff.powi(2);
-//         for (option, score) in &v.env_collision.active_obstacles[self.arm_idx] {
-//             if let Some(handle) = option {
-//                 let mut sum: f64 = 0.0;
-//                 let obstacle = v.env_collision.world.objects.get(*handle).unwrap();
-//                 let last_elem = frames[self.arm_idx].0.len() - 1;
-//                 for i in 0..last_elem {
-//                     let mut start_pt = Point3::from(frames[self.arm_idx].0[i]);
-//                      // hard coded for ur5
-//                     if (i == last_elem - 1) {
-//                         start_pt = Point3::from(frames[self.arm_idx].0[i] + 0.2 * (frames[self.arm_idx].0[i] - frames[self.arm_idx].0[i + 1]));
-//                     }
-
-//                     let end_pt = Point3::from(frames[self.arm_idx].0[i + 1]);
-//                     let segment = shape::Segment::new(start_pt, end_pt);
-//                     let segment_pos = nalgebra::one();
-//                     let dis = query::distance(obstacle.position(), obstacle.shape().deref(), &segment_pos, &segment) - link_radius;
-//                     // println!("Obstacle: {}, Link: {}, Distance: {:?}", obstacle.data().name, i, dis);
-//                     sum += a / (dis + link_radius).powi(2);
-//                 }
-//                 // println!("OBJECTIVE -> {:?}, Sum: {:?}", obstacle.data().name, sum);
-//                 x_val += sum;
-//             }
-//         }
-        
-//         // let end = PreciseTime::now();
-//         // println!("Obstacles calculating takes {}", start.to(end));
-
-//         groove_loss(x_val, 0., 2, 3.5, 0.00005, 4)
-//     }
-
-//     fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-//         let x_val = 1.0; // placeholder
-//         groove_loss(x_val, 0., 2, 2.1, 0.0002, 4)
-//     }
-// }
-
-pub struct MaximizeManipulability;
-impl ObjectiveTrait for MaximizeManipulability {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let x_val = v.robot.get_manipulability_immutable(&x);
-        groove_loss(x_val, 1.0, 2, 0.5, 0.1, 2)
+
+        self.groove.loss(x_val, 0.)
+    }
+}
+impl ObjectiveTrait for EnvCollision {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(x, v, &state.frames[self.arm_idx].0)
     }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        0.0
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.loss(x, v, &frames[self.arm_idx].0)
     }
+
+    fn gradient_type(&self) -> GradientType {GradientType::AutoDiff}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::FRAMES}
+}
+
+pub struct MaximizeManipulability {
+    pub groove: GrooveParams
+}
+impl MaximizeManipulability {
+    pub fn new() -> Self {Self{groove: GrooveParams::MANIPULABILITY}}
+}
+impl ObjectiveTrait for MaximizeManipulability {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        let mut x_val = 0.0;
+        for jacobian in &state.jacobians {
+            x_val += (jacobian * jacobian.transpose()).determinant().sqrt();
+        }
+        self.groove.loss(x_val, 1.0)
+    }
+
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::JOINTS.with_jacobians()}
 }
 pub struct EachJointLimits{
-    pub joint_idx: usize
+    pub joint_idx: usize,
+    pub swamp: SwampParams
 }
 impl EachJointLimits {
-    pub fn new(joint_idx: usize) -> Self {Self{joint_idx}}
+    pub fn new(joint_idx: usize) -> Self {Self{joint_idx, swamp: SwampParams::JOINT_LIMITS}}
+
+    fn loss<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars) -> T {
+        if v.robot.joints[self.joint_idx].lower_limit == -999.0 && v.robot.joints[self.joint_idx].upper_limit == 999.0 {
+            return T::from_f64(-1.0);
+        }
+        let l = v.robot.joints[self.joint_idx].lower_limit;
+        let u = v.robot.joints[self.joint_idx].upper_limit;
+        self.swamp.loss(x[self.joint_idx], l, u)
+    }
 }
 impl ObjectiveTrait for EachJointLimits {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-    
-        if v.robot.lower_joint_limits[self.joint_idx] == -999.0 && v.robot.upper_joint_limits[self.joint_idx] == 999.0 {
-            return -1.0;
-        }
-        let l = v.robot.lower_joint_limits[self.joint_idx];
-        let u = v.robot.upper_joint_limits[self.joint_idx];
-        swamp_loss(x[self.joint_idx], l, u, 10.0, 10.0, 20)
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(x, v)
+    }
+
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.loss(x, v)
     }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        0.0
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let mut grad = vec![0.0; x.len()];
+        if v.robot.joints[self.joint_idx].lower_limit == -999.0 && v.robot.joints[self.joint_idx].upper_limit == 999.0 {
+            return (-1.0, grad);
+        }
+        let l = v.robot.joints[self.joint_idx].lower_limit;
+        let u = v.robot.joints[self.joint_idx].upper_limit;
+        grad[self.joint_idx] = self.swamp.loss_derivative(x[self.joint_idx], l, u);
+        (self.swamp.loss(x[self.joint_idx], l, u), grad)
     }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::JOINTS}
 }
 
-pub struct MinimizeVelocity;
-impl ObjectiveTrait for MinimizeVelocity {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let mut x_val = 0.0;
+// Pulls the joints toward nominal_config, each weighted by posture_weights
+pub struct Posture {
+    pub groove: GrooveParams
+}
+impl Posture {
+    pub fn new() -> Self {Self{groove: GrooveParams::MOTION}}
+
+    fn weighted_distance<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars) -> T {
+        let mut sq = T::zero();
         for i in 0..x.len() {
-           x_val += (x[i] - v.xopt[i]).powi(2);
+            sq += (x[i] - v.nominal_config[i]).powi(2) * v.posture_weights[i];
         }
-        x_val = x_val.sqrt();
-        groove_loss(x_val, 0.0, 2, 0.1, 10.0, 2)
+        if sq.value() > 0.0 { sq.sqrt() } else { T::zero() }
+    }
+}
+impl ObjectiveTrait for Posture {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.groove.loss(self.weighted_distance(x, v), 0.0)
     }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let mut x_val = 0.0;
-        for i in 0..x.len() {
-           x_val += (x[i] - v.xopt[i]).powi(2);
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.groove.loss(self.weighted_distance(x, v), 0.0)
+    }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let x_val = self.weighted_distance(x, v);
+        let dloss = self.groove.loss_derivative(x_val, 0.0);
+        let mut grad = vec![0.0; x.len()];
+        if x_val > 0.0 {
+            for i in 0..x.len() {
+                grad[i] = dloss * v.posture_weights[i] * (x[i] - v.nominal_config[i]) / x_val;
+            }
         }
-        x_val = x_val.sqrt();
-        groove_loss(x_val, 0.0, 2, 0.1, 10.0, 2)
+        (self.groove.loss(x_val, 0.0), grad)
     }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::JOINTS}
 }
 
-pub struct MinimizeAcceleration;
-impl ObjectiveTrait for MinimizeAcceleration {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let mut x_val = 0.0;
+pub struct MinimizeVelocity {
+    pub groove: GrooveParams
+}
+impl MinimizeVelocity {
+    pub fn new() -> Self {Self{groove: GrooveParams::MOTION}}
+
+    fn loss<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars) -> T {
+        let mut x_val = T::zero();
         for i in 0..x.len() {
-            let v1 = x[i] - v.xopt[i];
-            let v2 = v.xopt[i] - v.prev_state[i];
-            x_val += (v1 - v2).powi(2);
+           x_val += (x[i] - v.xopt[i]).powi(2);
         }
         x_val = x_val.sqrt();
-        groove_loss(x_val, 0.0, 2, 0.1, 10.0, 2)
+        self.groove.loss(x_val, 0.0)
+    }
+}
+impl ObjectiveTrait for MinimizeVelocity {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(x, v)
     }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let mut x_val = 0.0;
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.loss(x, v)
+    }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let residuals: Vec<f64> = (0..x.len()).map(|i| x[i] - v.xopt[i]).collect();
+        norm_loss_gradient(&residuals, &self.groove)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::JOINTS}
+}
+
+pub struct MinimizeAcceleration {
+    pub groove: GrooveParams
+}
+impl MinimizeAcceleration {
+    pub fn new() -> Self {Self{groove: GrooveParams::MOTION}}
+
+    fn loss<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars) -> T {
+        let mut x_val = T::zero();
         for i in 0..x.len() {
             let v1 = x[i] - v.xopt[i];
             let v2 = v.xopt[i] - v.prev_state[i];
             x_val += (v1 - v2).powi(2);
         }
         x_val = x_val.sqrt();
-        groove_loss(x_val, 0.0, 2, 0.1, 10.0, 2)
+        self.groove.loss(x_val, 0.0)
     }
 }
+impl ObjectiveTrait for MinimizeAcceleration {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(x, v)
+    }
 
-pub struct MinimizeJerk;
-impl ObjectiveTrait for MinimizeJerk {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let mut x_val = 0.0;
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.loss(x, v)
+    }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let residuals: Vec<f64> = (0..x.len()).map(|i| (x[i] - v.xopt[i]) - (v.xopt[i] - v.prev_state[i])).collect();
+        norm_loss_gradient(&residuals, &self.groove)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::JOINTS}
+}
+
+pub struct MinimizeJerk {
+    pub groove: GrooveParams
+}
+impl MinimizeJerk {
+    pub fn new() -> Self {Self{groove: GrooveParams::MOTION}}
+
+    fn loss<T: Real>(&self, x: &[T], v: &vars::RelaxedIKVars) -> T {
+        let mut x_val = T::zero();
         for i in 0..x.len() {
             let v1 = x[i] - v.xopt[i];
             let v2 = v.xopt[i] - v.prev_state[i];
@@ -350,22 +635,30 @@ impl ObjectiveTrait for MinimizeJerk {
             x_val += (a1 - a2).powi(2);
         }
         x_val = x_val.sqrt();
-        groove_loss(x_val, 0.0, 2, 0.1 , 10.0, 2)
+        self.groove.loss(x_val, 0.0)
+    }
+}
+impl ObjectiveTrait for MinimizeJerk {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(x, v)
     }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let mut x_val = 0.0;
-        for i in 0..x.len() {
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        self.loss(x, v)
+    }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let residuals: Vec<f64> = (0..x.len()).map(|i| {
             let v1 = x[i] - v.xopt[i];
             let v2 = v.xopt[i] - v.prev_state[i];
             let v3 = v.prev_state[i] - v.prev_state2[i];
-            let a1 = v1 - v2;
-            let a2 = v2 - v3;
-            x_val += (a1 - a2).powi(2);
-        }
-        x_val = x_val.sqrt();
-        groove_loss(x_val, 0.0, 2, 0.1, 10.0, 2)
+            (v1 - v2) - (v2 - v3)
+        }).collect();
+        norm_loss_gradient(&residuals, &self.groove)
     }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::JOINTS}
 }
 
 
@@ -374,20 +667,39 @@ pub struct MatchEEPosGoals {
 }
 impl MatchEEPosGoals {
     pub fn new(arm_idx: usize) -> Self {Self{arm_idx}}
-}
-impl ObjectiveTrait for MatchEEPosGoals {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
-        let last_elem = frames[self.arm_idx].0.len() - 1;
-        let x_val = ( frames[self.arm_idx].0[last_elem] - v.goal_positions[self.arm_idx] ).norm();
+
+    fn loss<T: Real>(&self, ee_position: &Vector3<T>, v: &vars::RelaxedIKVars) -> T {
+        let x_val = vec3_norm(&( ee_position - vec3_from_f64(&v.goal_positions[self.arm_idx]) ));
 
         // groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
         quadratic_loss(x_val, 0., 2)
     }
+}
+impl ObjectiveTrait for MatchEEPosGoals {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(&state.ee_poses[self.arm_idx].0, v)
+    }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let x_val = ( ee_poses[self.arm_idx].0 - v.goal_positions[self.arm_idx] ).norm();
-        groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        let last_elem = frames[self.arm_idx].0.len() - 1;
+        self.loss(&frames[self.arm_idx].0[last_elem], v)
     }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let disp = state.ee_poses[self.arm_idx].0 - v.goal_positions[self.arm_idx];
+
+        let jacobian = &state.jacobians[self.arm_idx];
+        let mut grad = vec![0.0; x.len()];
+        for i in 0..x.len() {
+            let linear = Vector3::new(jacobian[(0, i)], jacobian[(1, i)], jacobian[(2, i)]);
+            grad[i] = 2.0 * disp.dot(&linear);
+        }
+        (quadratic_loss(disp.norm(), 0., 2), grad)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
 }
 
 
@@ -396,28 +708,204 @@ pub struct MatchEEQuatGoals {
 }
 impl MatchEEQuatGoals {
     pub fn new(arm_idx: usize) -> Self {Self{arm_idx}}
+
+    fn loss<T: Real>(&self, ee_quat: &Quat<T>, v: &vars::RelaxedIKVars) -> T {
+        // angle of the shortest rotation between the goal and ee orientations, q and -q are the same rotation
+        let goal_quat: Quat<T> = Quat::from_unit_quaternion(&v.goal_quats[self.arm_idx]);
+        let x_val = goal_quat.inverse().mul(ee_quat).angle();
+
+        // groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
+        quadratic_loss(x_val, 0., 2)
+    }
 }
 impl ObjectiveTrait for MatchEEQuatGoals {
-    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)>) -> f64 {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(&Quat::from_unit_quaternion(&state.ee_poses[self.arm_idx].1), v)
+    }
+
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
         let last_elem = frames[self.arm_idx].1.len() - 1;
-        let tmp = Quaternion::new(-frames[self.arm_idx].1[last_elem].w, -frames[self.arm_idx].1[last_elem].i, -frames[self.arm_idx].1[last_elem].j, -frames[self.arm_idx].1[last_elem].k);
-        let ee_quat2 = UnitQuaternion::from_quaternion(tmp);
+        self.loss(&frames[self.arm_idx].1[last_elem], v)
+    }
 
-        let disp = angle_between_quaternion(v.goal_quats[self.arm_idx], frames[self.arm_idx].1[last_elem]);
-        let disp2 = angle_between_quaternion(v.goal_quats[self.arm_idx], ee_quat2);
-        let x_val = disp.min(disp2);
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let goal_quat = v.goal_quats[self.arm_idx];
+        // the loss is the squared length of the rotation vector, whose gradient only sees
+        // the component of the inverse left jacobian along the rotation vector itself
+        let scaled_axis = (goal_quat.inverse() * state.ee_poses[self.arm_idx].1).scaled_axis();
 
-        // groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
-        quadratic_loss(x_val, 0., 2)
+        let jacobian = &state.jacobians[self.arm_idx];
+        let mut grad = vec![0.0; x.len()];
+        for i in 0..x.len() {
+            let angular = Vector3::new(jacobian[(3, i)], jacobian[(4, i)], jacobian[(5, i)]);
+            grad[i] = 2.0 * scaled_axis.dot(&(goal_quat.inverse() * angular));
+        }
+        (self.call(x, v, state), grad)
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
+}
+
+
+// Sensor axis of the end effector in the world frame and the vector from the end effector to the
+// look-at target
+fn look_at_geometry<T: Real>(ee_position: &Vector3<T>, ee_quat: &Quat<T>, arm_idx: usize, v: &vars::RelaxedIKVars) -> (Vector3<T>, Vector3<T>) {
+    let axis = ee_quat.rotate(&vec3_from_f64(&v.look_at_axes[arm_idx]));
+    let to_target = vec3_from_f64::<T>(&v.goal_look_at_points[arm_idx]) - ee_position;
+    (axis, to_target)
+}
+
+// Length of the chord between the sensor axis and the direction to the target on the unit sphere,
+// 2 sin(angle / 2), which unlike the angle itself is smooth where the two are aligned
+fn look_at_chord<T: Real>(axis: &Vector3<T>, to_target: &Vector3<T>) -> T {
+    let dist = vec3_norm(to_target);
+    let r = axis - to_target.map(|c| c / dist);
+    let sq = r.dot(&r);
+    if sq.value() > 0.0 { sq.sqrt() } else { T::zero() }
+}
+
+// Gradient of the look-at chord and of the distance to the target. The axis turns with the angular
+// velocity of the end effector and the direction to the target with its tangential linear velocity.
+fn look_at_chord_and_distance_gradients(ee_quat: &UnitQuaternion<f64>, to_target: &Vector3<f64>, arm_idx: usize, v: &vars::RelaxedIKVars,
+    jacobian: &DMatrix<f64>) -> (Vec<f64>, Vec<f64>) {
+    let axis = ee_quat * v.look_at_axes[arm_idx];
+    let dist = to_target.norm();
+    let dir = to_target / dist;
+    let r = axis - dir;
+    let chord = r.norm();
+    let mut chord_grad = vec![0.0; jacobian.ncols()];
+    let mut dist_grad = vec![0.0; jacobian.ncols()];
+    for i in 0..jacobian.ncols() {
+        let linear = Vector3::new(jacobian[(0, i)], jacobian[(1, i)], jacobian[(2, i)]);
+        let angular = Vector3::new(jacobian[(3, i)], jacobian[(4, i)], jacobian[(5, i)]);
+        let d_dir = -(linear - dir * dir.dot(&linear)) / dist;
+        if chord > 0.0 {
+            chord_grad[i] = r.dot(&(angular.cross(&axis) - d_dir)) / chord;
+        }
+        dist_grad[i] = -dir.dot(&linear);
+    }
+    (chord_grad, dist_grad)
+}
+
+// Points the sensor axis of the end effector (look_at_axes) at goal_look_at_points
+pub struct LookAtPoint {
+    pub arm_idx: usize,
+    pub groove: GrooveParams
+}
+impl LookAtPoint {
+    pub fn new(arm_idx: usize) -> Self {Self{arm_idx, groove: GrooveParams::MOTION}}
+
+    fn loss<T: Real>(&self, ee_position: &Vector3<T>, ee_quat: &Quat<T>, v: &vars::RelaxedIKVars) -> T {
+        let (axis, to_target) = look_at_geometry(ee_position, ee_quat, self.arm_idx, v);
+        self.groove.loss(look_at_chord(&axis, &to_target), 0.)
+    }
+}
+impl ObjectiveTrait for LookAtPoint {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        let (ee_position, ee_quat) = state.ee_poses[self.arm_idx];
+        self.loss(&ee_position, &Quat::from_unit_quaternion(&ee_quat), v)
     }
 
-    fn call_lite(&self, x: &[f64], v: &vars::RelaxedIKVars, ee_poses: &Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)>) -> f64 {
-        let tmp = Quaternion::new(-ee_poses[self.arm_idx].1.w, -ee_poses[self.arm_idx].1.i, -ee_poses[self.arm_idx].1.j, -ee_poses[self.arm_idx].1.k);
-        let ee_quat2 = UnitQuaternion::from_quaternion(tmp);
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        let (positions, quats) = &frames[self.arm_idx];
+        self.loss(&positions[positions.len() - 1], &quats[quats.len() - 1], v)
+    }
 
-        let disp = angle_between_quaternion(v.goal_quats[self.arm_idx], ee_poses[self.arm_idx].1);
-        let disp2 = angle_between_quaternion(v.goal_quats[self.arm_idx], ee_quat2);
-        let x_val = disp.min(disp2);
-        groove_loss(x_val, 0., 2, 0.1, 10.0, 2)
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let (ee_position, ee_quat) = state.ee_poses[self.arm_idx];
+        let (axis, to_target) = look_at_geometry(&ee_position, &Quat::from_unit_quaternion(&ee_quat), self.arm_idx, v);
+        let chord = look_at_chord(&axis, &to_target);
+        let (chord_grad, _) = look_at_chord_and_distance_gradients(&ee_quat, &to_target, self.arm_idx, v, &state.jacobians[self.arm_idx]);
+        let dloss = self.groove.loss_derivative(chord, 0.);
+        (self.groove.loss(chord, 0.), chord_grad.iter().map(|g| dloss * g).collect())
     }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
+}
+
+// Keeps the distance from the end effector to goal_look_at_points within look_at_distance_bounds
+pub struct LookAtDistance {
+    pub arm_idx: usize,
+    pub swamp: SwampParams
+}
+impl LookAtDistance {
+    pub fn new(arm_idx: usize) -> Self {Self{arm_idx, swamp: SwampParams::BAND}}
+
+    fn loss<T: Real>(&self, ee_position: &Vector3<T>, v: &vars::RelaxedIKVars) -> T {
+        let (l, u) = v.look_at_distance_bounds[self.arm_idx];
+        let to_target = vec3_from_f64::<T>(&v.goal_look_at_points[self.arm_idx]) - ee_position;
+        self.swamp.loss(vec3_norm(&to_target), l, u)
+    }
+}
+impl ObjectiveTrait for LookAtDistance {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        self.loss(&state.ee_poses[self.arm_idx].0, v)
+    }
+
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        let last_elem = frames[self.arm_idx].0.len() - 1;
+        self.loss(&frames[self.arm_idx].0[last_elem], v)
+    }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let (l, u) = v.look_at_distance_bounds[self.arm_idx];
+        let (ee_position, ee_quat) = state.ee_poses[self.arm_idx];
+        let to_target = v.goal_look_at_points[self.arm_idx] - ee_position;
+        let dist = to_target.norm();
+        let (_, dist_grad) = look_at_chord_and_distance_gradients(&ee_quat, &to_target, self.arm_idx, v, &state.jacobians[self.arm_idx]);
+        let dloss = self.swamp.loss_derivative(dist, l, u);
+        (self.swamp.loss(dist, l, u), dist_grad.iter().map(|g| dloss * g).collect())
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
+}
+
+// Keeps goal_look_at_points within look_at_cone_angles of the sensor axis
+pub struct ViewCone {
+    pub arm_idx: usize,
+    pub swamp: SwampParams
+}
+impl ViewCone {
+    pub fn new(arm_idx: usize) -> Self {Self{arm_idx, swamp: SwampParams::BAND}}
+
+    fn loss<T: Real>(&self, ee_position: &Vector3<T>, ee_quat: &Quat<T>, v: &vars::RelaxedIKVars) -> T {
+        let (axis, to_target) = look_at_geometry(ee_position, ee_quat, self.arm_idx, v);
+        let chord = look_at_chord(&axis, &to_target);
+        // angle = 2 asin(chord / 2)
+        let angle = (chord * 0.5).atan2((chord * chord * -0.25 + 1.0).sqrt()) * 2.0;
+        let cone_angle = v.look_at_cone_angles[self.arm_idx];
+        self.swamp.loss(angle, -cone_angle, cone_angle)
+    }
+}
+impl ObjectiveTrait for ViewCone {
+    fn call(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> f64 {
+        let (ee_position, ee_quat) = state.ee_poses[self.arm_idx];
+        self.loss(&ee_position, &Quat::from_unit_quaternion(&ee_quat), v)
+    }
+
+    fn call_dual(&self, x: &[Dual], v: &vars::RelaxedIKVars, frames: &Vec<(Vec<nalgebra::Vector3<Dual>>, Vec<Quat<Dual>>)>) -> Dual {
+        let (positions, quats) = &frames[self.arm_idx];
+        self.loss(&positions[positions.len() - 1], &quats[quats.len() - 1], v)
+    }
+
+    fn gradient(&self, x: &[f64], v: &vars::RelaxedIKVars, state: &KinematicState) -> (f64, Vec<f64>) {
+        let (ee_position, ee_quat) = state.ee_poses[self.arm_idx];
+        let (axis, to_target) = look_at_geometry(&ee_position, &Quat::from_unit_quaternion(&ee_quat), self.arm_idx, v);
+        let chord = look_at_chord(&axis, &to_target);
+        let angle = 2.0 * (chord / 2.0).min(1.0).asin();
+        let cone_angle = v.look_at_cone_angles[self.arm_idx];
+        let (chord_grad, _) = look_at_chord_and_distance_gradients(&ee_quat, &to_target, self.arm_idx, v, &state.jacobians[self.arm_idx]);
+        let dloss = self.swamp.loss_derivative(angle, -cone_angle, cone_angle) * 2.0 / (4.0 - chord * chord).sqrt();
+        (self.swamp.loss(angle, -cone_angle, cone_angle), chord_grad.iter().map(|g| dloss * g).collect())
+    }
+
+    fn gradient_type(&self) -> GradientType {GradientType::Analytic}
+    fn needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES}
+    fn gradient_needs(&self) -> KinematicNeeds {KinematicNeeds::EE_POSES.with_jacobians()}
 }
@@ -1,229 +1,345 @@
 use crate::groove::objective::*;
 use crate::groove::vars::RelaxedIKVars;
+use crate::utils_rust::yaml_utils::{ObjectiveSpec, ObjectiveOptions};
+use crate::utils_rust::autodiff::{DUAL_WIDTH, seed_duals};
 
 pub struct ObjectiveMaster {
     pub objectives: Vec<Box<dyn ObjectiveTrait + Send>>,
     pub num_chains: usize,
     pub weight_priors: Vec<f64>,
-    pub lite: bool,
+    // kind of the spec each objective was built from, used to look objectives up at runtime
+    pub names: Vec<String>,
+    // disabled objectives are skipped when evaluating the cost and its gradient
+    pub enabled: Vec<bool>,
     pub finite_diff_grad: bool
 }
 
 impl ObjectiveMaster {
     pub fn standard_ik(chain_indices: &[Vec<usize>]) -> Self {
-        let mut objectives: Vec<Box<dyn ObjectiveTrait + Send>> = Vec::new();
-        let mut weight_priors: Vec<f64> = Vec::new();
-        let num_chains = chain_indices.len();
-        for i in 0..num_chains {
-            objectives.push(Box::new(MatchEEPosGoals::new(i)));
-            weight_priors.push(10.0);
-            objectives.push(Box::new(MatchEEQuatGoals::new(i)));
-            weight_priors.push(1.0);
-        }
-        Self{objectives, num_chains, weight_priors, lite: false, finite_diff_grad: false}
+        Self::from_specs(&ObjectiveSpec::default_standard_ik(), chain_indices, false)
     }
 
+    pub fn relaxed_ik(chain_indices: &[Vec<usize>], env_collision: bool, self_collision: bool) -> Self {
+        let options = ObjectiveOptions{self_collision, ..ObjectiveOptions::default()};
+        Self::from_specs(&ObjectiveSpec::default_relaxed_ik(&options), chain_indices, env_collision)
+    }
 
-    pub fn relaxed_ik(chain_indices: &[Vec<usize>]) -> Self {
-        let mut objectives: Vec<Box<dyn ObjectiveTrait + Send>> = Vec::new();
-        let mut weight_priors: Vec<f64> = Vec::new();
-        let num_chains = chain_indices.len();
-        let mut num_dofs = 0;
-        for i in 0..num_chains {
-            objectives.push(Box::new(MatchEEPosiDoF::new(i, 0)));
-            weight_priors.push(50.0);
-            objectives.push(Box::new(MatchEEPosiDoF::new(i, 1)));
-            weight_priors.push(50.0);
-            objectives.push(Box::new(MatchEEPosiDoF::new(i, 2)));
-            weight_priors.push(50.0);
-            objectives.push(Box::new(MatchEERotaDoF::new(i, 0)));
-            weight_priors.push(1.0);
-            objectives.push(Box::new(MatchEERotaDoF::new(i, 1)));
-            weight_priors.push(1.0);
-            objectives.push(Box::new(MatchEERotaDoF::new(i, 2)));
-            weight_priors.push(1.0);
-
-            // objectives.push(Box::new(MatchEEPosGoals::new(i)));
-            // weight_priors.push(10.0);
-            // objectives.push(Box::new(MatchEEQuatGoals::new(i)));
-            // weight_priors.push(1.0);
-            // objectives.push(Box::new(EnvCollision::new(i)));
-            // weight_priors.push(1.0);
-        }
-
-        let num_dofs = chain_indices.iter().flat_map(|v| v.iter()).cloned().max().unwrap() + 1;
+    pub fn from_specs(specs: &[ObjectiveSpec], chain_indices: &[Vec<usize>], env_collision: bool) -> Self {
+        ObjectiveMasterBuilder::new(chain_indices, env_collision).specs(specs).build()
+    }
 
-        // for j in 0..num_dofs {
-        //     objectives.push(Box::new(EachJointLimits::new(j))); weight_priors.push(0.01 );
-        // }
+    pub fn builder(chain_indices: &[Vec<usize>], env_collision: bool) -> ObjectiveMasterBuilder {
+        ObjectiveMasterBuilder::new(chain_indices, env_collision)
+    }
 
-        objectives.push(Box::new(MinimizeVelocity));   weight_priors.push(0.01);
-        // objectives.push(Box::new(MinimizeAcceleration));    weight_priors.push(0.5);
-        // objectives.push(Box::new(MinimizeJerk));    weight_priors.push(0.3);
-        // objectives.push(Box::new(MaximizeManipulability));    weight_priors.push(1.0);
+    // Names of the objectives in order of first appearance, without repeats
+    pub fn objective_names(&self) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+        for name in &self.names {
+            if !out.contains(name) {
+                out.push(name.clone());
+            }
+        }
+        out
+    }
 
-        // for i in 0..num_chains {
-        //     for j in 0..chain_lengths[i]-2 {
-        //         for k in j+2..chain_lengths[i] {
-        //             objectives.push(Box::new(SelfCollision::new(0, j, k))); weight_priors.push(0.01 );
-        //         }
-        //     }
-        // }
-        
-        Self{objectives, num_chains, weight_priors, lite: false, finite_diff_grad: false}
+    pub fn get_weight(&self, name: &str) -> Option<f64> {
+        self.names.iter().position(|n| n == name).map(|i| self.weight_priors[i])
     }
 
-    pub fn call(&self, x: &[f64], vars: &RelaxedIKVars) -> f64 {
-        if self.lite {
-            self.__call_lite(x, vars)
-        } else {
-            self.__call(x, vars)
+    // Sets the weight of every objective with the given name; returns false if there is none
+    pub fn set_weight(&mut self, name: &str, weight: f64) -> bool {
+        let mut found = false;
+        for i in 0..self.names.len() {
+            if self.names[i] == name {
+                self.weight_priors[i] = weight;
+                found = true;
+            }
         }
+        found
     }
 
-    pub fn gradient(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
-        if self.lite {
-            if self.finite_diff_grad {
-                self.__gradient_finite_diff_lite(x, vars)
-            } else {
-                self.__gradient_lite(x, vars)
+    pub fn is_enabled(&self, name: &str) -> Option<bool> {
+        self.names.iter().position(|n| n == name).map(|i| self.enabled[i])
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for i in 0..self.names.len() {
+            if self.names[i] == name {
+                self.enabled[i] = enabled;
+                found = true;
             }
-        } else {
-            if self.finite_diff_grad {
-                self.__gradient_finite_diff(x, vars)
-            } else {
-                self.__gradient(x, vars)
+        }
+        found
+    }
+
+    // State read by the values of all objectives
+    pub fn needs(&self) -> KinematicNeeds {
+        let mut needs = KinematicNeeds::JOINTS;
+        for i in 0..self.objectives.len() {
+            if self.enabled[i] {
+                needs = needs.union(self.objectives[i].needs());
             }
         }
+        needs
     }
 
-    pub fn gradient_finite_diff(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
-        if self.lite {
-            self.__gradient_finite_diff_lite(x, vars)
+    pub fn call(&self, x: &[f64], vars: &RelaxedIKVars) -> f64 {
+        let state = KinematicState::new(&vars.robot, x, self.needs());
+        self.__call(x, vars, &state)
+    }
+
+    pub fn gradient(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
+        if self.finite_diff_grad {
+            self.gradient_finite_diff(x, vars)
         } else {
-            self.__gradient_finite_diff(x, vars)
+            self.__gradient(x, vars)
         }
     }
 
-    fn __call(&self, x: &[f64], vars: &RelaxedIKVars) -> f64 {
-        let mut out = 0.0;
-        let frames = vars.robot.get_frames_immutable(x);
-        for i in 0..self.objectives.len() {
-            out += self.weight_priors[i] * self.objectives[i].call(x, vars, &frames);
+    pub fn gradient_finite_diff(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
+        let needs = self.needs();
+        let mut grad: Vec<f64> = vec![0. ; x.len()];
+        let f_0 = self.call(x, vars);
+
+        for i in 0..x.len() {
+            let mut x_h = x.to_vec();
+            x_h[i] += 0.000001;
+            let state_h = KinematicState::new(&vars.robot, x_h.as_slice(), needs);
+            let f_h = self.__call(x_h.as_slice(), vars, &state_h);
+            grad[i] = (-f_0 + f_h) / 0.000001;
         }
-        out
+
+        (f_0, grad)
     }
 
-    fn __call_lite(&self, x: &[f64], vars: &RelaxedIKVars) -> f64 {
+    fn __call(&self, x: &[f64], vars: &RelaxedIKVars, state: &KinematicState) -> f64 {
         let mut out = 0.0;
-        let poses = vars.robot.get_ee_pos_and_quat_immutable(x);
         for i in 0..self.objectives.len() {
-            out += self.weight_priors[i] * self.objectives[i].call_lite(x, vars, &poses);
+            if self.enabled[i] {
+                out += self.weight_priors[i] * self.objectives[i].call(x, vars, state);
+            }
         }
         out
     }
 
+    // Each objective is differentiated the way it declares. The state at x is computed once for the
+    // analytic and finite difference objectives, each perturbation of x once for all finite
+    // difference objectives, and the dual frames once per DUAL_WIDTH joints for all automatically
+    // differentiated objectives.
     fn __gradient(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
         let mut grad: Vec<f64> = vec![0. ; x.len()];
         let mut obj = 0.0;
 
+        let mut needs_0 = KinematicNeeds::JOINTS;
+        let mut finite_diff_needs = KinematicNeeds::JOINTS;
+        let mut analytic_list: Vec<usize> = Vec::new();
         let mut finite_diff_list: Vec<usize> = Vec::new();
-        let mut f_0s: Vec<f64> = Vec::new();
-        let frames_0 = vars.robot.get_frames_immutable(x);
+        let mut autodiff_list: Vec<usize> = Vec::new();
         for i in 0..self.objectives.len() {
-            if self.objectives[i].gradient_type() == 0 {
-                let (local_obj, local_grad) = self.objectives[i].gradient(x, vars, &frames_0);
-                f_0s.push(local_obj);
-                obj += self.weight_priors[i] * local_obj;
-                for j in 0..local_grad.len() {
-                    grad[j] += self.weight_priors[i] * local_grad[j];
-                }
-            } else if self.objectives[i].gradient_type() == 1 {
-                finite_diff_list.push(i);
-                let local_obj = self.objectives[i].call(x, vars, &frames_0);
-                obj += self.weight_priors[i] * local_obj;
-                f_0s.push(local_obj);
+            if !self.enabled[i] {
+                continue;
+            }
+            match self.objectives[i].gradient_type() {
+                GradientType::Analytic => {
+                    analytic_list.push(i);
+                    needs_0 = needs_0.union(self.objectives[i].gradient_needs());
+                },
+                GradientType::FiniteDiff => {
+                    finite_diff_list.push(i);
+                    finite_diff_needs = finite_diff_needs.union(self.objectives[i].needs());
+                },
+                GradientType::AutoDiff => autodiff_list.push(i)
             }
         }
+        needs_0 = needs_0.union(finite_diff_needs);
 
-        if finite_diff_list.len() > 0 {
-            for i in 0..x.len() {
-                let mut x_h = x.to_vec();
-                x_h[i] += 0.0000001;
-                let frames_h = vars.robot.get_frames_immutable(x_h.as_slice());
-                for j in &finite_diff_list {
-                    let f_h = self.objectives[*j].call(&x_h, vars, &frames_h);
-                    grad[i] += self.weight_priors[*j] * ((-f_0s[*j] + f_h) /  0.0000001);
-                }
+        let state_0 = KinematicState::new(&vars.robot, x, needs_0);
+        for i in &analytic_list {
+            let (local_obj, local_grad) = self.objectives[*i].gradient(x, vars, &state_0);
+            obj += self.weight_priors[*i] * local_obj;
+            for j in 0..local_grad.len() {
+                grad[j] += self.weight_priors[*i] * local_grad[j];
             }
         }
 
-        (obj, grad)
-    }
-
-    fn __gradient_lite(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
-        let mut grad: Vec<f64> = vec![0. ; x.len()];
-        let mut obj = 0.0;
-
-        let mut finite_diff_list: Vec<usize> = Vec::new();
-        let mut f_0s: Vec<f64> = Vec::new();
-        let poses_0 = vars.robot.get_ee_pos_and_quat_immutable(x);
-        for i in 0..self.objectives.len() {
-            if self.objectives[i].gradient_type() == 1 {
-                let (local_obj, local_grad) = self.objectives[i].gradient_lite(x, vars, &poses_0);
-                f_0s.push(local_obj);
-                obj += self.weight_priors[i] * local_obj;
-                for j in 0..local_grad.len() {
-                    grad[j] += self.weight_priors[i] * local_grad[j];
+        // one forward kinematics pass with dual numbers gives the derivatives for DUAL_WIDTH joints
+        if !autodiff_list.is_empty() {
+            for offset in (0..x.len().max(1)).step_by(DUAL_WIDTH) {
+                let x_dual = seed_duals(x, offset);
+                let frames_dual = vars.robot.get_frames_generic(&x_dual);
+                for j in &autodiff_list {
+                    let out = self.objectives[*j].call_dual(&x_dual, vars, &frames_dual);
+                    if offset == 0 {
+                        obj += self.weight_priors[*j] * out.re;
+                    }
+                    for k in offset..x.len().min(offset + DUAL_WIDTH) {
+                        grad[k] += self.weight_priors[*j] * out.eps[k - offset];
+                    }
                 }
-            } else if self.objectives[i].gradient_type() == 0 {
-                finite_diff_list.push(i);
-                let local_obj = self.objectives[i].call_lite(x, vars, &poses_0);
-                obj += self.weight_priors[i] * local_obj;
-                f_0s.push(local_obj);
             }
         }
 
-        if finite_diff_list.len() > 0 {
+        if !finite_diff_list.is_empty() {
+            let f_0s: Vec<f64> = finite_diff_list.iter().map(|j| self.objectives[*j].call(x, vars, &state_0)).collect();
+            for j in 0..finite_diff_list.len() {
+                obj += self.weight_priors[finite_diff_list[j]] * f_0s[j];
+            }
             for i in 0..x.len() {
                 let mut x_h = x.to_vec();
                 x_h[i] += 0.0000001;
-                let poses_h = vars.robot.get_ee_pos_and_quat_immutable(x_h.as_slice());
-                for j in &finite_diff_list {
-                    let f_h = self.objectives[*j].call_lite(x, vars, &poses_h);
-                    grad[i] += self.weight_priors[*j] * ((-f_0s[*j] + f_h) /  0.0000001);
+                let state_h = KinematicState::new(&vars.robot, x_h.as_slice(), finite_diff_needs);
+                for j in 0..finite_diff_list.len() {
+                    let f_h = self.objectives[finite_diff_list[j]].call(&x_h, vars, &state_h);
+                    grad[i] += self.weight_priors[finite_diff_list[j]] * ((-f_0s[j] + f_h) /  0.0000001);
                 }
             }
         }
 
         (obj, grad)
     }
+}
 
-    fn __gradient_finite_diff(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>)  {
-        let mut grad: Vec<f64> = vec![0. ; x.len()];
-        let mut f_0 = self.call(x, vars);
+// Assembles an ObjectiveMaster from configured objective kinds and user objectives, e.g.
+// ObjectiveMaster::builder(&chain_indices, false)
+//     .specs(&ObjectiveSpec::default_relaxed_ik(&ObjectiveOptions::default()))
+//     .objective("camera_look_at", Box::new(my_objective), 5.0)
+//     .build()
+pub struct ObjectiveMasterBuilder {
+    pub num_chains: usize,
+    pub num_dofs: usize,
+    pub env_collision: bool,
+    pub objectives: Vec<Box<dyn ObjectiveTrait + Send>>,
+    pub weight_priors: Vec<f64>,
+    pub names: Vec<String>,
+    pub finite_diff_grad: bool
+}
 
-        for i in 0..x.len() {
-            let mut x_h = x.to_vec();
-            x_h[i] += 0.000001;
-            let f_h = self.call(x_h.as_slice(), vars);
-            grad[i] = (-f_0 + f_h) / 0.000001;
-        }
+impl ObjectiveMasterBuilder {
+    // env_collision objectives are skipped unless env_collision is set (there are obstacles)
+    pub fn new(chain_indices: &[Vec<usize>], env_collision: bool) -> Self {
+        let num_chains = chain_indices.len();
+        let num_dofs = chain_indices.iter().flat_map(|v| v.iter()).cloned().max().unwrap() + 1;
+        Self{num_chains, num_dofs, env_collision, objectives: Vec::new(), weight_priors: Vec::new(), names: Vec::new(), finite_diff_grad: false}
+    }
 
-        (f_0, grad)
+    // Adds a user objective under the given name, which the runtime weight API looks it up by
+    pub fn objective(mut self, name: &str, objective: Box<dyn ObjectiveTrait + Send>, weight: f64) -> Self {
+        self.objectives.push(objective);
+        self.weight_priors.push(weight);
+        self.names.push(name.to_string());
+        self
     }
 
-    fn __gradient_finite_diff_lite(&self, x: &[f64], vars: &RelaxedIKVars) -> (f64, Vec<f64>) {
-        let mut grad: Vec<f64> = vec![0. ; x.len()];
-        let mut f_0 = self.call(x, vars);
+    pub fn specs(mut self, specs: &[ObjectiveSpec]) -> Self {
+        for spec in specs {
+            self = self.spec(spec);
+        }
+        self
+    }
 
-        for i in 0..x.len() {
-            let mut x_h = x.to_vec();
-            x_h[i] += 0.000001;
-            let f_h = self.__call_lite(x_h.as_slice(), vars);
-            grad[i] = (-f_0 + f_h) / 0.000001;
+    // Per-chain kinds add one objective for every chain (or every axis of every chain) and
+    // joint_limits adds one per joint.
+    pub fn spec(mut self, spec: &ObjectiveSpec) -> Self {
+        let groove = |default: GrooveParams| spec.groove.unwrap_or(default);
+        let swamp = |default: SwampParams| spec.swamp.unwrap_or(default);
+        let mut new_objectives: Vec<Box<dyn ObjectiveTrait + Send>> = Vec::new();
+        match spec.kind.as_str() {
+            "ee_position_dofs" => for i in 0..self.num_chains {
+                for axis in 0..3 {
+                    let mut o = MatchEEPosiDoF::new(i, axis);
+                    o.groove = groove(o.groove);
+                    new_objectives.push(Box::new(o));
+                }
+            },
+            "ee_rotation_dofs" => for i in 0..self.num_chains {
+                for axis in 0..3 {
+                    let mut o = MatchEERotaDoF::new(i, axis);
+                    o.groove = groove(o.groove);
+                    o.swamp = swamp(o.swamp);
+                    new_objectives.push(Box::new(o));
+                }
+            },
+            "ee_position" => for i in 0..self.num_chains {
+                new_objectives.push(Box::new(MatchEEPosGoals::new(i)));
+            },
+            "ee_orientation" => for i in 0..self.num_chains {
+                new_objectives.push(Box::new(MatchEEQuatGoals::new(i)));
+            },
+            "look_at" => for i in 0..self.num_chains {
+                let mut o = LookAtPoint::new(i);
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "look_at_distance" => for i in 0..self.num_chains {
+                let mut o = LookAtDistance::new(i);
+                o.swamp = swamp(o.swamp);
+                new_objectives.push(Box::new(o));
+            },
+            "view_cone" => for i in 0..self.num_chains {
+                let mut o = ViewCone::new(i);
+                o.swamp = swamp(o.swamp);
+                new_objectives.push(Box::new(o));
+            },
+            "env_collision" => if self.env_collision {
+                for i in 0..self.num_chains {
+                    let mut o = EnvCollision::new(i);
+                    o.groove = groove(o.groove);
+                    new_objectives.push(Box::new(o));
+                }
+            },
+            "self_collision" => {
+                let mut o = SelfCollision::new();
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "velocity" => {
+                let mut o = MinimizeVelocity::new();
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "acceleration" => {
+                let mut o = MinimizeAcceleration::new();
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "jerk" => {
+                let mut o = MinimizeJerk::new();
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "manipulability" => {
+                let mut o = MaximizeManipulability::new();
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "posture" => {
+                let mut o = Posture::new();
+                o.groove = groove(o.groove);
+                new_objectives.push(Box::new(o));
+            },
+            "joint_limits" => for j in 0..self.num_dofs {
+                let mut o = EachJointLimits::new(j);
+                o.swamp = swamp(o.swamp);
+                new_objectives.push(Box::new(o));
+            },
+            kind => panic!("Unknown objective kind {} in settings", kind)
+        }
+        for o in new_objectives {
+            self = self.objective(&spec.kind, o, spec.weight);
         }
+        self
+    }
 
-        (f_0, grad)
+    pub fn finite_diff_grad(mut self, finite_diff_grad: bool) -> Self {
+        self.finite_diff_grad = finite_diff_grad;
+        self
     }
-}
\ No newline at end of file
+
+    pub fn build(self) -> ObjectiveMaster {
+        let enabled = vec![true; self.objectives.len()];
+        ObjectiveMaster{objectives: self.objectives, num_chains: self.num_chains, weight_priors: self.weight_priors,
+            names: self.names, enabled, finite_diff_grad: self.finite_diff_grad}
+    }
+}
@@ -0,0 +1,490 @@
+use crate::spacetime::robot_kinematics::RobotKinematics;
+use nalgebra::{Vector3, Isometry3, Point3, Matrix4};
+use nalgebra::geometry::{Translation3, UnitQuaternion};
+use parry3d_f64::shape::{Segment, SharedShape};
+use parry3d_f64::query;
+use parry3d_f64::query::ClosestPoints;
+use crate::utils_rust::autodiff::{Real, Quat, vec3_from_f64, vec3_value, vec3_norm};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Clone)]
+pub struct CollisionLink {
+    pub name: String,
+    pub arm_idx: usize,
+    pub frame_idx: usize,
+    // shapes with their poses in the link frame
+    pub shapes: Vec<(Isometry3<f64>, SharedShape)>,
+    // the geometry in the urdf could not be loaded and is approximated by a capsule
+    pub approximated: bool,
+}
+
+#[derive(Clone)]
+pub struct RelaxedIKSelfCollision {
+    pub links: Vec<CollisionLink>,
+    pub link_radius: f64,
+    // pairs of indices into links that are checked, every other pair is allowed to collide
+    pub active_pairs: Vec<(usize, usize)>,
+    // pairs farther apart than this are ignored by the collision objective
+    pub proximity_distance: f64,
+    // whether active_pairs has been narrowed down by compute_allowed_collision_matrix
+    pub acm_computed: bool,
+    adjacent_pairs: Vec<(usize, usize)>,
+}
+
+impl RelaxedIKSelfCollision {
+    // Builds collision shapes from the <collision> elements of the links in each chain. Meshes are
+    // replaced by their convex hull; links whose geometry cannot be loaded are approximated by a
+    // capsule of link_radius between the link origin and the next joint origin.
+    pub fn from_urdf(description: &urdf_rs::Robot, robot: &RobotKinematics, link_radius: f64, mesh_path: &str) -> Self {
+        let urdf_links: HashMap<&str, &urdf_rs::Link> = description.links.iter().map(|l| (l.name.as_str(), l)).collect();
+
+        let mut links: Vec<CollisionLink> = Vec::new();
+        let mut adjacent_pairs: Vec<(usize, usize)> = Vec::new();
+        for arm_idx in 0..robot.num_chains {
+            let arm = &robot.arms[arm_idx];
+            let mut prev_link: Option<usize> = None;
+            for frame_idx in 0..arm.link_names.len() {
+                let name = &arm.link_names[frame_idx];
+                let urdf_link = match urdf_links.get(name.as_str()) {
+                    Some(l) if !l.collision.is_empty() => l,
+                    _ => continue
+                };
+
+                // links shared between chains (e.g. a torso) are only added once
+                let link_idx = match links.iter().position(|l| l.name == *name) {
+                    Some(idx) => idx,
+                    None => {
+                        let mut shapes: Vec<(Isometry3<f64>, SharedShape)> = Vec::new();
+                        for collision in &urdf_link.collision {
+                            if let Some((local_pose, shape)) = geometry_to_shape(&collision.geometry, mesh_path) {
+                                shapes.push((pose_to_isometry(&collision.origin) * local_pose, shape));
+                            }
+                        }
+                        let approximated = shapes.is_empty();
+                        if approximated {
+                            println!("Warning: could not load collision geometry of link {}, approximating it with a capsule", name);
+                            shapes.push(link_capsule(arm.displacements.get(frame_idx), link_radius));
+                        }
+                        links.push(CollisionLink { name: name.clone(), arm_idx, frame_idx, shapes, approximated });
+                        links.len() - 1
+                    }
+                };
+
+                // links connected through links without collision geometry are also adjacent
+                if let Some(prev) = prev_link {
+                    adjacent_pairs.push((prev.min(link_idx), prev.max(link_idx)));
+                }
+                prev_link = Some(link_idx);
+            }
+        }
+
+        let mut active_pairs: Vec<(usize, usize)> = Vec::new();
+        for i in 0..links.len() {
+            for j in i + 1..links.len() {
+                if !adjacent_pairs.contains(&(i, j)) {
+                    active_pairs.push((i, j));
+                }
+            }
+        }
+
+        println!("Loaded collision geometry of {} links, {} approximated by capsules", links.len(), links.iter().filter(|l| l.approximated).count());
+
+        Self{links, link_radius, active_pairs, proximity_distance: 10.0 * link_radius, acm_computed: false, adjacent_pairs}
+    }
+
+    // Similar to the MoveIt setup assistant: besides adjacent links, pairs that collide in the starting
+    // config, collide in almost all random configs, or never collide in any random config are allowed
+    pub fn compute_allowed_collision_matrix(&mut self, robot: &RobotKinematics, starting_config: &[f64], num_samples: usize) {
+        let num_pairs = self.links.len() * self.links.len().saturating_sub(1) / 2;
+
+        let frames = robot.get_frames_immutable(starting_config);
+        let poses = self.link_poses(&frames);
+        let mut candidates: Vec<(usize, usize)> = Vec::new();
+        for (i, j) in &self.active_pairs {
+            if !self.pair_in_collision(&poses, *i, *j) {
+                candidates.push((*i, *j));
+            }
+        }
+        let num_default = self.active_pairs.len() - candidates.len();
+
+        self.acm_computed = true;
+        if num_samples == 0 {
+            self.active_pairs = candidates;
+            println!("Self-collision: {} of {} link pairs are checked", self.active_pairs.len(), num_pairs);
+            return;
+        }
+
+        // a fixed seed keeps the pairs that are never in collision, and so not checked, the same
+        // from run to run
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = vec![0; candidates.len()];
+        for _ in 0..num_samples {
+            let x = random_config(robot, &mut rng);
+            let frames = robot.get_frames_immutable(&x);
+            let poses = self.link_poses(&frames);
+            for k in 0..candidates.len() {
+                if self.pair_in_collision(&poses, candidates[k].0, candidates[k].1) {
+                    counts[k] += 1;
+                }
+            }
+        }
+
+        let mut active_pairs: Vec<(usize, usize)> = Vec::new();
+        let mut num_always = 0;
+        let mut num_never = 0;
+        for k in 0..candidates.len() {
+            if counts[k] as f64 >= 0.95 * num_samples as f64 {
+                num_always += 1;
+            } else if counts[k] == 0 {
+                num_never += 1;
+            } else {
+                active_pairs.push(candidates[k]);
+            }
+        }
+        self.active_pairs = active_pairs;
+
+        println!("Self-collision: {} of {} link pairs are checked ({} adjacent, {} in collision by default, {} always in collision, {} never in collision)",
+            self.active_pairs.len(), num_pairs, self.adjacent_pairs.len(), num_default, num_always, num_never);
+    }
+
+    pub fn link_poses(&self, frames: &[(Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>)]) -> Vec<Isometry3<f64>> {
+        self.links.iter().map(|link| {
+            let (positions, quats) = &frames[link.arm_idx];
+            Isometry3::from_parts(Translation3::from(positions[link.frame_idx]), quats[link.frame_idx])
+        }).collect()
+    }
+
+    pub fn pair_distance(&self, poses: &[Isometry3<f64>], link1: usize, link2: usize) -> f64 {
+        let mut dis = f64::INFINITY;
+        for (local_pose_1, shape_1) in &self.links[link1].shapes {
+            for (local_pose_2, shape_2) in &self.links[link2].shapes {
+                let d = query::distance(&(poses[link1] * local_pose_1), shape_1.as_ref(), &(poses[link2] * local_pose_2), shape_2.as_ref()).unwrap();
+                dis = dis.min(d);
+            }
+        }
+        dis
+    }
+
+    pub fn pair_in_collision(&self, poses: &[Isometry3<f64>], link1: usize, link2: usize) -> bool {
+        for (local_pose_1, shape_1) in &self.links[link1].shapes {
+            for (local_pose_2, shape_2) in &self.links[link2].shapes {
+                if query::intersection_test(&(poses[link1] * local_pose_1), shape_1.as_ref(), &(poses[link2] * local_pose_2), shape_2.as_ref()).unwrap() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // Distances between all checked link pairs, in the order of active_pairs
+    pub fn distances(&self, frames: &[(Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>)]) -> Vec<f64> {
+        let poses = self.link_poses(frames);
+        self.active_pairs.iter().map(|(i, j)| self.pair_distance(&poses, *i, *j)).collect()
+    }
+
+    // Distances of the checked link pairs closer than proximity_distance, for frames over any Real
+    // scalar. Each distance is linearized at the closest points of the two links, which is exact
+    // to first order, so dual numbers carry its derivatives.
+    pub fn proximity_distances_generic<T: Real>(&self, frames: &[(Vec<Vector3<T>>, Vec<Quat<T>>)]) -> Vec<T> {
+        let poses: Vec<Isometry3<f64>> = self.links.iter().map(|link| {
+            let (positions, quats) = &frames[link.arm_idx];
+            Isometry3::from_parts(Translation3::from(vec3_value(&positions[link.frame_idx])), quats[link.frame_idx].value())
+        }).collect();
+
+        let mut out: Vec<T> = Vec::new();
+        for (i, j) in &self.active_pairs {
+            let mut closest: Option<(f64, Point3<f64>, Point3<f64>, f64)> = None;
+            let mut intersecting = false;
+            for (local_pose_1, shape_1) in &self.links[*i].shapes {
+                for (local_pose_2, shape_2) in &self.links[*j].shapes {
+                    let (points, radius) = closest_points(&(poses[*i] * local_pose_1), shape_1, &(poses[*j] * local_pose_2), shape_2, self.proximity_distance);
+                    match points {
+                        ClosestPoints::Intersecting => intersecting = true,
+                        ClosestPoints::WithinMargin(p1, p2) => {
+                            let d = (p2 - p1).norm() - radius;
+                            if closest.is_none_or(|(d_min, _, _, _)| d < d_min) {
+                                closest = Some((d, p1, p2, radius));
+                            }
+                        },
+                        ClosestPoints::Disjoint => {}
+                    }
+                }
+            }
+
+            if intersecting {
+                out.push(T::zero());
+            } else if let Some((_, p1, p2, radius)) = closest {
+                // closest points move rigidly with their links
+                let local_1 = poses[*i].inverse_transform_point(&p1);
+                let local_2 = poses[*j].inverse_transform_point(&p2);
+                let (positions_1, quats_1) = &frames[self.links[*i].arm_idx];
+                let (positions_2, quats_2) = &frames[self.links[*j].arm_idx];
+                let p1 = quats_1[self.links[*i].frame_idx].rotate(&vec3_from_f64(&local_1.coords)) + positions_1[self.links[*i].frame_idx];
+                let p2 = quats_2[self.links[*j].frame_idx].rotate(&vec3_from_f64(&local_2.coords)) + positions_2[self.links[*j].frame_idx];
+                out.push(vec3_norm(&(p2 - p1)) - radius);
+            }
+        }
+        out
+    }
+
+    pub fn min_distance(&self, frames: &[(Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>)]) -> f64 {
+        self.distances(frames).iter().fold(f64::INFINITY, |acc, d| acc.min(*d))
+    }
+
+    pub fn check_collision(&self, frames: &[(Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>)]) -> bool {
+        let poses = self.link_poses(frames);
+        self.active_pairs.iter().any(|(i, j)| self.pair_in_collision(&poses, *i, *j))
+    }
+}
+
+fn random_config(robot: &RobotKinematics, rng: &mut impl Rng) -> Vec<f64> {
+    let mut x = Vec::with_capacity(robot.num_dofs);
+    for i in 0..robot.num_dofs {
+        if robot.joints[i].joint_type == "continuous" {
+            x.push(rng.gen_range(-std::f64::consts::PI..std::f64::consts::PI));
+        } else {
+            x.push(rng.gen_range(robot.joints[i].lower_limit..=robot.joints[i].upper_limit));
+        }
+    }
+    x
+}
+
+// Capsules and balls as a segment in the shape frame and a radius
+fn rounded_segment(shape: &SharedShape) -> Option<(Point3<f64>, Point3<f64>, f64)> {
+    if let Some(capsule) = shape.as_capsule() {
+        Some((capsule.segment.a, capsule.segment.b, capsule.radius))
+    } else {
+        shape.as_ball().map(|ball| (Point3::origin(), Point3::origin(), ball.radius))
+    }
+}
+
+// Closest points between two shapes within max_dist, with the radius to subtract from their distance.
+// GJK only converges approximately on curved shapes, so pairs of capsules and balls are computed
+// exactly from the closest points of their segments, which are returned instead of surface points.
+fn closest_points(pos1: &Isometry3<f64>, shape1: &SharedShape, pos2: &Isometry3<f64>, shape2: &SharedShape, max_dist: f64) -> (ClosestPoints, f64) {
+    if let (Some((a1, b1, r1)), Some((a2, b2, r2))) = (rounded_segment(shape1), rounded_segment(shape2)) {
+        let (a1, b1, a2, b2) = (pos1 * a1, pos1 * b1, pos2 * a2, pos2 * b2);
+        let (loc1, loc2) = query::closest_points::closest_points_segment_segment_with_locations_nD((&a1, &b1), (&a2, &b2));
+        let p1 = Segment::new(a1, b1).point_at(&loc1);
+        let p2 = Segment::new(a2, b2).point_at(&loc2);
+        let d = (p2 - p1).norm() - r1 - r2;
+        let points = if d <= 0.0 {
+            ClosestPoints::Intersecting
+        } else if d <= max_dist {
+            ClosestPoints::WithinMargin(p1, p2)
+        } else {
+            ClosestPoints::Disjoint
+        };
+        return (points, r1 + r2);
+    }
+    match query::closest_points(pos1, shape1.as_ref(), pos2, shape2.as_ref(), max_dist).unwrap() {
+        // the points are only accurate up to the GJK tolerance along the surfaces, alternating exact
+        // projections moves them onto the closest features so their direction matches the normal
+        ClosestPoints::WithinMargin(mut p1, mut p2) => {
+            for _ in 0..3 {
+                p1 = project_point(pos1, shape1, &p2);
+                p2 = project_point(pos2, shape2, &p1);
+            }
+            (ClosestPoints::WithinMargin(p1, p2), 0.0)
+        },
+        points => (points, 0.0)
+    }
+}
+
+// Closest point of a shape to pt. parry projects onto cylinders with GJK, which is not exact,
+// so they are projected analytically.
+pub fn project_point(pos: &Isometry3<f64>, shape: &SharedShape, pt: &Point3<f64>) -> Point3<f64> {
+    match shape.as_cylinder() {
+        Some(cylinder) => {
+            let mut local = pos.inverse_transform_point(pt);
+            local.y = local.y.max(-cylinder.half_height).min(cylinder.half_height);
+            let radial = (local.x.powi(2) + local.z.powi(2)).sqrt();
+            if radial > cylinder.radius {
+                local.x *= cylinder.radius / radial;
+                local.z *= cylinder.radius / radial;
+            }
+            pos * local
+        },
+        None => shape.project_point(pos, pt, true).point
+    }
+}
+
+fn pose_to_isometry(pose: &urdf_rs::Pose) -> Isometry3<f64> {
+    Isometry3::from_parts(
+        Translation3::new(pose.xyz[0], pose.xyz[1], pose.xyz[2]),
+        UnitQuaternion::from_euler_angles(pose.rpy[0], pose.rpy[1], pose.rpy[2]))
+}
+
+fn link_capsule(next_joint_offset: Option<&Vector3<f64>>, link_radius: f64) -> (Isometry3<f64>, SharedShape) {
+    match next_joint_offset {
+        Some(offset) if offset.norm() > 1e-6 => (Isometry3::identity(), SharedShape::capsule(Point3::origin(), Point3::from(*offset), link_radius)),
+        _ => (Isometry3::identity(), SharedShape::ball(link_radius))
+    }
+}
+
+fn geometry_to_shape(geometry: &urdf_rs::Geometry, mesh_path: &str) -> Option<(Isometry3<f64>, SharedShape)> {
+    match geometry {
+        urdf_rs::Geometry::Box { size } => Some((Isometry3::identity(), SharedShape::cuboid(size[0] / 2.0, size[1] / 2.0, size[2] / 2.0))),
+        urdf_rs::Geometry::Sphere { radius } => Some((Isometry3::identity(), SharedShape::ball(*radius))),
+        // urdf cylinders and capsules are aligned with the z axis, parry cylinders with the y axis
+        urdf_rs::Geometry::Cylinder { radius, length } => {
+            let y_to_z = Isometry3::rotation(Vector3::x() * std::f64::consts::FRAC_PI_2);
+            Some((y_to_z, SharedShape::cylinder(length / 2.0, *radius)))
+        },
+        urdf_rs::Geometry::Capsule { radius, length } => {
+            let a = Point3::new(0.0, 0.0, -length / 2.0);
+            let b = Point3::new(0.0, 0.0, length / 2.0);
+            Some((Isometry3::identity(), SharedShape::capsule(a, b, *radius)))
+        },
+        urdf_rs::Geometry::Mesh { filename, scale } => {
+            let scale = scale.as_ref().map(|s| [s[0], s[1], s[2]]).unwrap_or([1.0, 1.0, 1.0]);
+            let points = load_mesh_vertices(&resolve_mesh_path(filename, mesh_path))?;
+            let points: Vec<Point3<f64>> = points.iter().map(|p| Point3::new(p[0] * scale[0], p[1] * scale[1], p[2] * scale[2])).collect();
+            Some((Isometry3::identity(), SharedShape::convex_hull(&points)?))
+        }
+    }
+}
+
+// package://<package>/<path> and relative paths are looked up in mesh_path
+fn resolve_mesh_path(filename: &str, mesh_path: &str) -> String {
+    if let Some(path) = filename.strip_prefix("package://") {
+        format!("{}{}", mesh_path, path)
+    } else if let Some(path) = filename.strip_prefix("file://") {
+        path.to_string()
+    } else if Path::new(filename).is_absolute() {
+        filename.to_string()
+    } else {
+        format!("{}{}", mesh_path, filename)
+    }
+}
+
+// STL and COLLADA (.dae) meshes are supported
+fn load_mesh_vertices(path: &str) -> Option<Vec<[f64; 3]>> {
+    let extension = path.to_lowercase();
+    if extension.ends_with(".stl") {
+        let mut file = File::open(path).ok()?;
+        let mesh = stl_io::read_stl(&mut file).ok()?;
+        Some(mesh.vertices.iter().map(|v| [v[0] as f64, v[1] as f64, v[2] as f64]).collect())
+    } else if extension.ends_with(".dae") {
+        let text = std::fs::read_to_string(path).ok()?;
+        let points = load_collada_vertices(&text)?;
+        if points.is_empty() { None } else { Some(points) }
+    } else {
+        None
+    }
+}
+
+// Vertex positions of the geometries in the visual scene of a COLLADA file, transformed by their
+// nodes, scaled to meters and rotated so that the up axis of the file is z, as rviz displays them.
+// Only positions are read since the mesh is replaced by its convex hull.
+fn load_collada_vertices(text: &str) -> Option<Vec<[f64; 3]>> {
+    let doc = roxmltree::Document::parse(text).ok()?;
+    let root = doc.root_element();
+
+    let asset = child(root, "asset");
+    let meter = asset.and_then(|a| child(a, "unit")).and_then(|u| u.attribute("meter"))
+        .and_then(|m| m.trim().parse::<f64>().ok()).unwrap_or(1.0);
+    let up_axis = asset.and_then(|a| child(a, "up_axis")).and_then(|u| u.text()).unwrap_or("Y_UP").trim().to_string();
+    let to_z_up = match up_axis.as_str() {
+        "X_UP" => Matrix4::new(0.0, 0.0, -1.0, 0.0,  0.0, 1.0, 0.0, 0.0,  1.0, 0.0, 0.0, 0.0,  0.0, 0.0, 0.0, 1.0),
+        "Z_UP" => Matrix4::identity(),
+        _ => Matrix4::new(1.0, 0.0, 0.0, 0.0,  0.0, 0.0, -1.0, 0.0,  0.0, 1.0, 0.0, 0.0,  0.0, 0.0, 0.0, 1.0),
+    };
+    let root_transform = Matrix4::new_scaling(meter) * to_z_up;
+
+    let mut geometries: HashMap<&str, Vec<Point3<f64>>> = HashMap::new();
+    for geometry in doc.descendants().filter(|n| n.has_tag_name("geometry")) {
+        if let (Some(id), Some(positions)) = (geometry.attribute("id"), child(geometry, "mesh").and_then(collada_mesh_positions)) {
+            geometries.insert(id, positions);
+        }
+    }
+
+    let scene = child(root, "scene").and_then(|s| child(s, "instance_visual_scene"))
+        .and_then(|i| i.attribute("url")).and_then(|url| find_by_id(&doc, url))
+        .or_else(|| doc.descendants().find(|n| n.has_tag_name("visual_scene")));
+    let mut points: Vec<[f64; 3]> = Vec::new();
+    let mut add = |positions: &[Point3<f64>], transform: &Matrix4<f64>| {
+        for p in positions {
+            let p = transform.transform_point(p);
+            points.push([p.x, p.y, p.z]);
+        }
+    };
+    match scene {
+        Some(scene) => {
+            let mut stack: Vec<(roxmltree::Node, Matrix4<f64>, usize)> =
+                scene.children().filter(|n| n.has_tag_name("node")).map(|n| (n, root_transform, 0)).collect();
+            while let Some((node, parent_transform, depth)) = stack.pop() {
+                let mut transform = parent_transform;
+                for element in node.children().filter(|n| n.is_element()) {
+                    let values = parse_floats(element.text().unwrap_or(""));
+                    match element.tag_name().name() {
+                        "matrix" if values.len() == 16 => transform *= Matrix4::from_row_slice(&values),
+                        "translate" if values.len() == 3 => transform *= Matrix4::new_translation(&Vector3::new(values[0], values[1], values[2])),
+                        "scale" if values.len() == 3 => transform *= Matrix4::new_nonuniform_scaling(&Vector3::new(values[0], values[1], values[2])),
+                        "rotate" if values.len() == 4 => {
+                            let axis = Vector3::new(values[0], values[1], values[2]);
+                            transform *= UnitQuaternion::from_scaled_axis(axis.normalize() * values[3].to_radians()).to_homogeneous();
+                        },
+                        _ => {}
+                    }
+                }
+                for element in node.children().filter(|n| n.is_element()) {
+                    match element.tag_name().name() {
+                        "instance_geometry" => {
+                            let url = element.attribute("url").unwrap_or("");
+                            if let Some(positions) = geometries.get(url.trim_start_matches('#')) {
+                                add(positions, &transform);
+                            }
+                        },
+                        "node" => stack.push((element, transform, depth + 1)),
+                        // instanced nodes may refer back to their ancestors
+                        "instance_node" if depth < 32 => {
+                            if let Some(instanced) = element.attribute("url").and_then(|url| find_by_id(&doc, url)) {
+                                stack.push((instanced, transform, depth + 1));
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+            }
+        },
+        None => {
+            for positions in geometries.values() {
+                add(positions, &root_transform);
+            }
+        }
+    }
+    Some(points)
+}
+
+// The float_array of the source that the POSITION input of the <vertices> of a <mesh> refers to
+fn collada_mesh_positions(mesh: roxmltree::Node) -> Option<Vec<Point3<f64>>> {
+    let source_id = child(mesh, "vertices")?.children()
+        .find(|n| n.has_tag_name("input") && n.attribute("semantic") == Some("POSITION"))?
+        .attribute("source")?.trim_start_matches('#');
+    let source = mesh.children().find(|n| n.has_tag_name("source") && n.attribute("id") == Some(source_id))?;
+    let values = parse_floats(child(source, "float_array")?.text().unwrap_or(""));
+    let stride = child(source, "technique_common").and_then(|t| child(t, "accessor"))
+        .and_then(|a| a.attribute("stride")).and_then(|s| s.parse::<usize>().ok()).unwrap_or(3);
+    if stride < 3 {
+        return None;
+    }
+    Some(values.chunks_exact(stride).map(|v| Point3::new(v[0], v[1], v[2])).collect())
+}
+
+fn child<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn find_by_id<'a, 'input>(doc: &'a roxmltree::Document<'input>, url: &str) -> Option<roxmltree::Node<'a, 'input>> {
+    let id = url.trim_start_matches('#');
+    doc.descendants().find(|n| n.attribute("id") == Some(id))
+}
+
+fn parse_floats(text: &str) -> Vec<f64> {
+    text.split_whitespace().filter_map(|v| v.parse::<f64>().ok()).collect()
+}
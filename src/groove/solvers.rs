@@ -0,0 +1,220 @@
+use nalgebra::{DMatrix, DVector};
+use crate::groove::vars::RelaxedIKVars;
+use crate::groove::objective_master::ObjectiveMaster;
+use crate::groove::groove::{IKSolver, OptimizationEngineOpen, SolverOptions, SolveResult, ExitReason};
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolverKind {
+    Panoc,
+    DampedLeastSquares,
+    ProjectedGradient,
+}
+
+impl SolverKind {
+    pub fn build(&self, dim: usize, options: SolverOptions) -> Box<dyn IKSolver + Send> {
+        match self {
+            SolverKind::Panoc => Box::new(OptimizationEngineOpen::with_options(dim, options)),
+            SolverKind::DampedLeastSquares => Box::new(DampedLeastSquares::new(options)),
+            SolverKind::ProjectedGradient => Box::new(ProjectedGradient::new(options)),
+        }
+    }
+}
+
+fn clamp_to_limits(x: &mut [f64], v: &RelaxedIKVars) {
+    for (i, xi) in x.iter_mut().enumerate() {
+        *xi = xi.clamp(v.robot.joints[i].lower_limit, v.robot.joints[i].upper_limit);
+    }
+}
+
+fn out_of_time(start: &Instant, options: &SolverOptions) -> bool {
+    options.time_budget.is_some_and(|budget| start.elapsed() > budget)
+}
+
+// Levenberg-Marquardt on the end effector pose errors of all chains. It only reads goal_positions
+// and goal_quats, so it suits pure pose reaching; the objective master is only used to report the
+// cost of the solution.
+pub struct DampedLeastSquares {
+    options: SolverOptions,
+    pub initial_damping: f64,
+    pub min_relative_decrease: f64,
+}
+
+impl DampedLeastSquares {
+    pub fn new(options: SolverOptions) -> Self {
+        Self{options, initial_damping: 0.01, min_relative_decrease: 1e-3}
+    }
+
+    // Stacked position and rotation vector errors in the world frame, 6 per chain
+    fn pose_error(&self, x: &[f64], v: &RelaxedIKVars) -> DVector<f64> {
+        let poses = v.robot.get_ee_pos_and_quat_immutable(x);
+        let mut error = DVector::zeros(6 * poses.len());
+        for (i, (ee_pos, ee_quat)) in poses.iter().enumerate() {
+            error.fixed_rows_mut::<3>(6 * i).copy_from(&(v.goal_positions[i] - ee_pos));
+            error.fixed_rows_mut::<3>(6 * i + 3).copy_from(&(v.goal_quats[i] * ee_quat.inverse()).scaled_axis());
+        }
+        error
+    }
+}
+
+impl IKSolver for DampedLeastSquares {
+    fn options(&self) -> SolverOptions {
+        self.options
+    }
+
+    fn set_options(&mut self, options: SolverOptions) {
+        self.options = options;
+    }
+
+    fn optimize(&mut self, x: &mut [f64], v: &RelaxedIKVars, om: &ObjectiveMaster, max_iter: usize) -> SolveResult {
+        let start = Instant::now();
+        clamp_to_limits(x, v);
+        let mut error = self.pose_error(x, v);
+        let mut damping = self.initial_damping;
+        let mut iterations = 0;
+        let mut exit_reason = ExitReason::MaxIterations;
+        while iterations < max_iter {
+            if error.norm() < self.options.tolerance {
+                exit_reason = ExitReason::Converged;
+                break;
+            }
+            if out_of_time(&start, &self.options) {
+                exit_reason = ExitReason::OutOfTime;
+                break;
+            }
+            iterations += 1;
+
+            // (J^T J + damping^2 I) dx = J^T e
+            let jacobian = v.robot.get_jacobian_immutable(x);
+            let jt = jacobian.transpose();
+            let lhs = &jt * &jacobian + DMatrix::identity(x.len(), x.len()) * damping.powi(2);
+            let dx = match lhs.cholesky() {
+                Some(cholesky) => cholesky.solve(&(&jt * &error)),
+                None => {
+                    exit_reason = ExitReason::NotFinite;
+                    break;
+                }
+            };
+
+            let mut x_new = x.to_vec();
+            for i in 0..x.len() {
+                x_new[i] += dx[i];
+            }
+            clamp_to_limits(&mut x_new, v);
+            let error_new = self.pose_error(&x_new, v);
+            if !error_new.norm().is_finite() {
+                exit_reason = ExitReason::NotFinite;
+                break;
+            }
+
+            if error_new.norm() < error.norm() {
+                let decrease = 1.0 - error_new.norm() / error.norm();
+                x.copy_from_slice(&x_new);
+                error = error_new;
+                damping = (damping * 0.5).max(1e-6);
+                // stalled, at the goal only if the last step reached the tolerance
+                if decrease < self.min_relative_decrease {
+                    exit_reason = if error.norm() < self.options.tolerance { ExitReason::Converged } else { ExitReason::Stalled };
+                    break;
+                }
+            } else {
+                // no damped step decreases the error, e.g. against the joint limits
+                damping *= 4.0;
+                if damping > 1e6 {
+                    exit_reason = ExitReason::Stalled;
+                    break;
+                }
+            }
+        }
+
+        SolveResult{iterations, converged: exit_reason == ExitReason::Converged, cost: om.call(x, v), exit_reason, solve_time: start.elapsed()}
+    }
+}
+
+// Gradient descent on the objective master with an Armijo backtracking line search, projected
+// onto the joint limits
+pub struct ProjectedGradient {
+    options: SolverOptions,
+    step_size: f64,
+}
+
+impl ProjectedGradient {
+    pub fn new(options: SolverOptions) -> Self {
+        Self{options, step_size: 1.0}
+    }
+}
+
+impl IKSolver for ProjectedGradient {
+    fn options(&self) -> SolverOptions {
+        self.options
+    }
+
+    fn set_options(&mut self, options: SolverOptions) {
+        self.options = options;
+    }
+
+    fn optimize(&mut self, x: &mut [f64], v: &RelaxedIKVars, om: &ObjectiveMaster, max_iter: usize) -> SolveResult {
+        let start = Instant::now();
+        clamp_to_limits(x, v);
+        let (mut cost, mut grad) = om.gradient(x, v);
+        let mut iterations = 0;
+        let mut exit_reason = ExitReason::MaxIterations;
+        while iterations < max_iter {
+            if out_of_time(&start, &self.options) {
+                exit_reason = ExitReason::OutOfTime;
+                break;
+            }
+            if !cost.is_finite() || grad.iter().any(|g| !g.is_finite()) {
+                exit_reason = ExitReason::NotFinite;
+                break;
+            }
+            iterations += 1;
+
+            // the step grows again after every accepted step, so a short step is not kept forever
+            let mut step_size = (self.step_size * 2.0).min(1.0);
+            let mut accepted = None;
+            for _ in 0..40 {
+                let mut x_new = x.to_vec();
+                for i in 0..x.len() {
+                    x_new[i] -= step_size * grad[i];
+                }
+                clamp_to_limits(&mut x_new, v);
+                let decrease: f64 = grad.iter().zip(x.iter().zip(x_new.iter())).map(|(g, (a, b))| g * (a - b)).sum();
+                let cost_new = om.call(&x_new, v);
+                if cost_new <= cost - 1e-4 * decrease {
+                    accepted = Some(x_new);
+                    break;
+                }
+                step_size *= 0.5;
+            }
+            self.step_size = step_size;
+
+            let x_new = match accepted {
+                Some(x_new) => x_new,
+                None => {
+                    // no step decreases the cost, which is only convergence at a stationary point
+                    let mut x_step = x.to_vec();
+                    for i in 0..x.len() {
+                        x_step[i] -= grad[i];
+                    }
+                    clamp_to_limits(&mut x_step, v);
+                    let residual = x_step.iter().zip(x.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt();
+                    exit_reason = if residual < self.options.tolerance { ExitReason::Converged } else { ExitReason::Stalled };
+                    break;
+                }
+            };
+            // norm of the projected gradient step, which vanishes at a stationary point within the limits
+            let residual = x_new.iter().zip(x.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt() / step_size;
+            x.copy_from_slice(&x_new);
+            let (cost_new, grad_new) = om.gradient(x, v);
+            cost = cost_new;
+            grad = grad_new;
+            if residual < self.options.tolerance {
+                exit_reason = ExitReason::Converged;
+                break;
+            }
+        }
+
+        SolveResult{iterations, converged: exit_reason == ExitReason::Converged, cost, exit_reason, solve_time: start.elapsed()}
+    }
+}
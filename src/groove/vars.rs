@@ -1,6 +1,10 @@
 use nalgebra::{UnitQuaternion, Vector3, Vector6, Quaternion, Point3};
 use crate::spacetime::robot_kinematics::RobotKinematics;
+use crate::spacetime::branches::BranchTest;
 use crate::utils_rust::file_utils::{*};
+use crate::utils_rust::yaml_utils::{EnvCollisionFileParser, ObjectiveFileParser, ObjectiveSpec, ObjectiveOptions, yaml_to_f64, yaml_to_vec3, yaml_to_pose};
+use crate::groove::env_collision::RelaxedIKEnvCollision;
+use crate::groove::self_collision::RelaxedIKSelfCollision;
 use time::PreciseTime;
 use std::ops::Deref;
 use yaml_rust::{YamlLoader, Yaml};
@@ -30,7 +34,25 @@ pub struct RelaxedIKVars {
     pub goal_quats: Vec<UnitQuaternion<f64>>,
     pub tolerances: Vec<Vector6<f64>>,
     pub init_ee_positions: Vec<Vector3<f64>>,
-    pub init_ee_quats: Vec<UnitQuaternion<f64>>
+    pub init_ee_quats: Vec<UnitQuaternion<f64>>,
+    pub env_collision: RelaxedIKEnvCollision,
+    pub self_collision: RelaxedIKSelfCollision,
+    // random configs of compute_allowed_collision_matrix
+    pub self_collision_samples: usize,
+    pub objective_options: ObjectiveOptions,
+    pub relaxed_ik_objectives: Vec<ObjectiveSpec>,
+    pub standard_ik_objectives: Vec<ObjectiveSpec>,
+    // sensor axis in the end effector frame, target point and its allowed distances and angle
+    // from the axis, read by the look-at objectives
+    pub look_at_axes: Vec<Vector3<f64>>,
+    pub goal_look_at_points: Vec<Vector3<f64>>,
+    pub look_at_distance_bounds: Vec<(f64, f64)>,
+    pub look_at_cone_angles: Vec<f64>,
+    // preferred configuration and per-joint weights of the posture objective
+    pub nominal_config: Vec<f64>,
+    pub posture_weights: Vec<f64>,
+    // named joint sign tests from branch_flags, which replace the geometric branch tests of the arm
+    pub branch_tests: Vec<(String, BranchTest)>
 }
 impl RelaxedIKVars {
     pub fn from_local_settings(path_to_setting: &str) -> Self {
@@ -41,7 +63,7 @@ impl RelaxedIKVars {
         let docs = YamlLoader::load_from_str(contents.as_str()).unwrap();
         let settings = &docs[0];
 
-        let path_to_urdf = path_to_src + "configs/urdfs/" + settings["urdf"].as_str().unwrap();
+        let path_to_urdf = path_to_src.clone() + "configs/urdfs/" + settings["urdf"].as_str().unwrap();
         println!("RelaxedIK is using below URDF file: {}", path_to_urdf);
 
         let base_links_arr = settings["base_links"].as_vec().unwrap();
@@ -58,7 +80,7 @@ impl RelaxedIKVars {
         };
 
         let urdf = &std::fs::read_to_string(path_to_urdf).unwrap();        
-        let robot;
+        let mut robot;
         let mut starting_config = Vec::new();
 
         let num_chains;
@@ -74,6 +96,17 @@ impl RelaxedIKVars {
         }
 
         robot = RobotKinematics::from_urdf(urdf, &base_links, &ee_links, joint_ordering);
+        // goals, obstacles and forward kinematics are in the world frame at the tool center point
+        if let Some(base_pose) = yaml_to_pose(&settings["base_pose"]) {
+            robot.set_base_pose(base_pose);
+        }
+        robot.set_tcp_offset(yaml_to_pose(&settings["tcp_offset"]));
+        let joint_limits = |key: &str| -> Vec<(String, f64)> {
+            settings[key].as_hash().map(|limits| limits.iter().map(|(name, limit)| (name.as_str().unwrap().to_string(),
+                yaml_to_f64(limit).unwrap_or_else(|| panic!("Invalid limit of joint {:?} in {}", name, key)))).collect()).unwrap_or_default()
+        };
+        robot.set_joint_limits(&joint_limits("velocity_limits"), &joint_limits("acceleration_limits"),
+            yaml_to_f64(&settings["velocity_scaling"]).unwrap_or(1.0), yaml_to_f64(&settings["acceleration_scaling"]).unwrap_or(1.0));
         if settings["starting_config"].is_badvalue() {
             println!("No starting config provided, using all zeros");
             for i in 0..robot.num_dofs {
@@ -99,9 +132,93 @@ impl RelaxedIKVars {
         println!("init_ee_positions: {:?}", init_ee_positions);
         println!("init_ee_quats: {:?}", init_ee_quats);
 
-        RelaxedIKVars{robot, init_state: starting_config.clone(), xopt: starting_config.clone(),
+        let env_collision_file = EnvCollisionFileParser::from_yaml(settings);
+        let link_radius = env_collision_file.robot_link_radius;
+        let env_collision = RelaxedIKEnvCollision::init_collision_world(env_collision_file);
+
+        // package:// mesh paths are resolved relative to mesh_path
+        let mut mesh_path = match settings["mesh_path"].as_str() {
+            Some(path) if path.starts_with('/') => path.to_string(),
+            Some(path) => path_to_src + path,
+            None => path_to_src + "configs/meshes/"
+        };
+        if !mesh_path.ends_with('/') {
+            mesh_path.push('/');
+        }
+        let self_collision_samples = settings["self_collision_samples"].as_i64().unwrap_or(5000) as usize;
+        let description: urdf_rs::Robot = urdf_rs::read_from_string(urdf).unwrap();
+        let self_collision = RelaxedIKSelfCollision::from_urdf(&description, &robot, link_radius, &mesh_path);
+        let objective_options = ObjectiveOptions::from_yaml(settings);
+        let objective_file = ObjectiveFileParser::from_yaml(settings, &objective_options);
+
+        let look_at = &settings["look_at"];
+        let look_at_axis = yaml_to_vec3(&look_at["axis"], [0.0, 0.0, 1.0]);
+        let look_at_distance = match look_at["distance"].as_vec() {
+            Some(arr) => (yaml_to_f64(&arr[0]).unwrap(), yaml_to_f64(&arr[1]).unwrap()),
+            None => (0.1, 1.0)
+        };
+        assert!(look_at_distance.0 < look_at_distance.1, "look_at distance should be [min, max], got {:?}", look_at_distance);
+        let look_at_cone_angle = yaml_to_f64(&look_at["cone_angle"]).unwrap_or(0.1);
+        let look_at_axes = vec![Vector3::new(look_at_axis[0], look_at_axis[1], look_at_axis[2]).normalize(); num_chains];
+        let look_at_distance_bounds = vec![look_at_distance; num_chains];
+        let goal_look_at_points = Self::look_at_points_ahead(&init_ee_positions, &init_ee_quats, &look_at_axes, &look_at_distance_bounds);
+
+        // by default the posture is the middle of the joint limits, and continuous joints have no preference
+        let posture = &settings["posture"];
+        let nominal_config: Vec<f64> = match posture["nominal_config"].as_vec() {
+            Some(arr) => arr.iter().map(|v| yaml_to_f64(v).unwrap()).collect(),
+            None => (0..robot.num_dofs).map(|i| if robot.joints[i].joint_type == "continuous" { 0.0 }
+                else { (robot.joints[i].lower_limit + robot.joints[i].upper_limit) / 2.0 }).collect()
+        };
+        let posture_weights: Vec<f64> = match posture["weights"].as_vec() {
+            Some(arr) => arr.iter().map(|v| yaml_to_f64(v).unwrap()).collect(),
+            None => (0..robot.num_dofs).map(|i| if robot.joints[i].joint_type == "continuous" { 0.0 } else { 1.0 }).collect()
+        };
+        assert!(nominal_config.len() == robot.num_dofs, "Invalid number of joints in posture nominal_config {}, expected {}", nominal_config.len(), robot.num_dofs);
+        assert!(posture_weights.len() == robot.num_dofs, "Invalid number of joints in posture weights {}, expected {}", posture_weights.len(), robot.num_dofs);
+
+        let mut branch_tests = Vec::new();
+        if let Some(flags) = settings["branch_flags"].as_hash() {
+            for (name, flag) in flags {
+                let joint = flag["joint"].as_str().expect("branch_flags entries need a joint");
+                let dof = robot.joints.iter().position(|joint_info| joint_info.name == joint)
+                    .unwrap_or_else(|| panic!("Unknown joint {} in branch_flags", joint));
+                let offset = yaml_to_f64(&flag["offset"]).unwrap_or(0.0);
+                branch_tests.push((name.as_str().unwrap().to_string(), BranchTest::JointSign{dof, offset}));
+            }
+        }
+
+        let mut vars = RelaxedIKVars{robot, init_state: starting_config.clone(), xopt: starting_config.clone(),
             prev_state: starting_config.clone(), prev_state2: starting_config.clone(), prev_state3: starting_config.clone(),
-            goal_positions: init_ee_positions.clone(), goal_quats: init_ee_quats.clone(), tolerances, init_ee_positions, init_ee_quats}
+            goal_positions: init_ee_positions.clone(), goal_quats: init_ee_quats.clone(), tolerances, init_ee_positions, init_ee_quats, env_collision,
+            self_collision, self_collision_samples, objective_options,
+            relaxed_ik_objectives: objective_file.relaxed_ik, standard_ik_objectives: objective_file.standard_ik,
+            look_at_axes, goal_look_at_points, look_at_distance_bounds, look_at_cone_angles: vec![look_at_cone_angle; num_chains],
+            nominal_config, posture_weights, branch_tests};
+
+        // the allowed-collision matrix takes thousands of samples, so it is only computed here for the
+        // self_collision objective, and otherwise by whoever checks self-collisions (e.g. IKLink)
+        if vars.relaxed_ik_objectives.iter().chain(vars.standard_ik_objectives.iter()).any(|spec| spec.kind == "self_collision") {
+            vars.compute_allowed_collision_matrix();
+        }
+        vars
+    }
+
+    // Must be called before self-collisions are checked, or every pair but adjacent links is checked.
+    // Pairs in collision at init_state are allowed, so it is meant to be called before reset().
+    pub fn compute_allowed_collision_matrix(&mut self) {
+        if !self.self_collision.acm_computed {
+            self.self_collision.compute_allowed_collision_matrix(&self.robot, &self.init_state, self.self_collision_samples);
+        }
+    }
+
+    // Points along the sensor axes in the middle of the distance bands, which the initial pose looks at
+    pub fn look_at_points_ahead(ee_positions: &[Vector3<f64>], ee_quats: &[UnitQuaternion<f64>], axes: &[Vector3<f64>], distance_bounds: &[(f64, f64)]) -> Vec<Vector3<f64>> {
+        let mut out = Vec::new();
+        for i in 0..ee_positions.len() {
+            out.push(ee_positions[i] + ee_quats[i] * axes[i] * (distance_bounds[i].0 + distance_bounds[i].1) / 2.0);
+        }
+        out
     }
     
     // for webassembly
@@ -162,6 +279,7 @@ impl RelaxedIKVars {
         self.init_ee_quats = init_ee_quats.clone();
         self.goal_positions = init_ee_positions.clone();
         self.goal_quats = init_ee_quats.clone();
+        self.goal_look_at_points = Self::look_at_points_ahead(&init_ee_positions, &init_ee_quats, &self.look_at_axes, &self.look_at_distance_bounds);
         for _ in self.tolerances.len()..self.goal_positions.len() {
             self.tolerances.push(Vector6::new(0., 0., 0., 0., 0., 0.));
         }
@@ -2,8 +2,13 @@
 use std::vec;
 
 use crate::spacetime::robot::Robot;
-use nalgebra::{UnitQuaternion, Vector3};
+use crate::relaxed_ik::{RelaxedIK, ObjectiveMasterFactory};
+use crate::groove::groove::ExitReason;
+use crate::groove::solvers::SolverKind;
+use nalgebra::{UnitQuaternion, Vector3, Isometry3, Point3};
 use crate::spacetime::motion::Motion;
+use crate::spacetime::branches::BranchLabel;
+use std::collections::HashSet;
 use linfa_clustering::Dbscan;
 use linfa::traits::*;
 use ndarray::{Array1, Array2};
@@ -13,6 +18,7 @@ pub struct Node {
     pub primary_score: f64,
     pub secondary_score: f64,
     pub predecessor: usize,
+    pub branch: BranchLabel,
 }
 
 impl Node {
@@ -27,13 +33,36 @@ impl Node {
             primary_score,
             secondary_score,
             predecessor,
+            branch: BranchLabel::default(),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackingMode {
+    // the trajectory lists end effector poses
+    Pose,
+    // the trajectory lists points for the sensor axis to look at, its orientations are ignored
+    LookAt,
+}
+
 pub struct IKLink {
     pub robot: Robot,
     pub trajectory: Vec<(f64, Vector3<f64>, UnitQuaternion<f64>)>,
+    // pose of the workpiece frame the trajectory is given in, in the world frame of the robot
+    pub workpiece_pose: Isometry3<f64>,
+    pub mode: TrackingMode,
+    // times an unconverged random restart is continued before giving up on it
+    pub max_continuations: usize,
+    // random restarts tried per point before giving up on it, e.g. when it can only be reached in
+    // collision
+    pub max_restarts: usize,
+    // nodes per point, which bounds the cost of the dynamic programming
+    pub max_nodes: usize,
+    // candidates sampled along the self-motion manifold of each random restart of a redundant arm,
+    // and their joint space spacing
+    pub null_space_samples: usize,
+    pub null_space_spacing: f64,
 
     pub table: Vec<Vec<Node>>,
 
@@ -48,6 +77,11 @@ impl IKLink {
 
         let n = self.trajectory.len();
 
+        // a point without nodes cannot be passed, not even with a reconfiguration
+        if let Some(i) = self.table.iter().position(|nodes| nodes.is_empty()) {
+            panic!("No valid solution found! Point {} has no collision free IK solution", i);
+        }
+
         // first column
         for y in 0..self.table[0].len() {
             self.table[0][y].primary_score = 0.0;
@@ -83,10 +117,11 @@ impl IKLink {
                 let mut predecessor = min_idx_with_config;
                 for y2 in 0..self.table[x-1].len() {
 
-                    if self.robot.check_velocity(&self.table[x][y1].ik, &self.table[x-1][y2].ik, delta_t) {
+                    if self.robot.check_velocity(&self.table[x][y1].ik, &self.table[x-1][y2].ik, delta_t) && self.check_acceleration(x, y1, y2) {
                         let primary_score = self.table[x-1][y2].primary_score;
                         let secondary_score = self.table[x-1][y2].secondary_score + self.robot.joint_movement(&self.table[x][y1].ik, &self.table[x-1][y2].ik);
-                        if primary_score < min_primary_score {
+                        // the swept motion is only checked for transitions that would be selected
+                        if primary_score < min_primary_score && !self.robot.check_motion_collision(&self.table[x-1][y2].ik, &self.table[x][y1].ik, 0.05) {
                             min_primary_score = primary_score;
                             min_secondary_score = secondary_score;
                             predecessor = y2;
@@ -118,23 +153,42 @@ impl IKLink {
 
         let mut motion = Motion {
             robot_name: self.robot.robot_name.clone(),
-            joint_names: self.robot.ik_solver.vars.robot.joint_names.clone(),
+            joint_names: self.robot.ik_solver.vars.robot.joint_names(),
             data: vec![],
+            branch_labels: vec![],
         };
 
         // backtrace
         let mut idx: usize = best_idx;
         for i in (0..n).rev() {
             motion.data.push((self.trajectory[i].0, self.table[i][idx].ik.clone()));
+            motion.branch_labels.push(self.table[i][idx].branch.clone());
             idx = self.table[i][idx].predecessor;
         }
 
         motion.data.reverse();
+        motion.branch_labels.reverse();
         
         motion
     }
 
 
+    // Acceleration through node y2 of point x-1 into node y1 of point x, following the predecessor
+    // of y2. Always true if y2 was reached with a reconfiguration, where the motion restarts.
+    fn check_acceleration(&self, x: usize, y1: usize, y2: usize) -> bool {
+        if x < 2 {
+            return true;
+        }
+        let prev = &self.table[x-1][y2];
+        let prev_prev = &self.table[x-2][prev.predecessor];
+        if prev.primary_score > prev_prev.primary_score {
+            return true;
+        }
+        let delta_t = self.trajectory[x].0 - self.trajectory[x-1].0;
+        let prev_delta_t = self.trajectory[x-1].0 - self.trajectory[x-2].0;
+        self.robot.check_acceleration(&self.table[x][y1].ik, &prev.ik, &prev_prev.ik, delta_t, prev_delta_t)
+    }
+
     fn vec_of_arrays_to_2d_array(&self, vec: &mut Vec<Array1<f64>>) -> Array2<f64> {
         if vec.is_empty() {
             return Array2::zeros((0, 0)); // Return an empty 2D array if the input vector is empty
@@ -157,6 +211,121 @@ impl IKLink {
         array2d
     }
 
+    // Pose (or look-at target) of point i in the world frame
+    fn goal(&self, i: usize) -> (Vector3<f64>, UnitQuaternion<f64>) {
+        let (_, pos, quat) = self.trajectory[i];
+        ((self.workpiece_pose * Point3::from(pos)).coords, self.workpiece_pose.rotation * quat)
+    }
+
+    // A solve that runs out of iterations is still making progress and is continued from where it
+    // stopped. A solve that converges or stalls away from the pose is in a local minimum, so the
+    // next call restarts from a new random config.
+    fn try_to_reach(&mut self, i: usize) -> (bool, Array1<f64>) {
+        let (pos, quat) = self.goal(i);
+        let (mut found_ik, mut ik, mut result) = match self.mode {
+            TrackingMode::Pose => self.robot.try_to_reach(pos, quat),
+            TrackingMode::LookAt => self.robot.try_to_reach_look_at(pos)
+        };
+        let mut num_continuations = 0;
+        while !found_ik && result.exit_reason == ExitReason::MaxIterations && num_continuations < self.max_continuations {
+            (found_ik, ik, result) = match self.mode {
+                TrackingMode::Pose => self.robot.continue_to_reach(pos, quat),
+                TrackingMode::LookAt => self.robot.continue_to_reach_look_at(pos)
+            };
+            num_continuations += 1;
+        }
+        (found_ik, ik)
+    }
+
+    // Adds the collision free analytic IK solutions of point i that no node is already close to, up
+    // to max_nodes nodes, and returns how many were added. If there are more solutions than room,
+    // they are taken in turn from each branch, evenly spaced over the redundancy parameter.
+    fn add_analytic_candidates(&mut self, i: usize) -> usize {
+        let (pos, quat) = self.goal(i);
+        let mut branches: Vec<Vec<Node>> = Vec::new();
+        for ik in self.robot.try_to_reach_analytic(pos, quat) {
+            if self.robot.check_collision(&ik) {
+                continue;
+            }
+            let node = self.new_node(ik);
+            if self.is_duplicate(i, &node) {
+                continue;
+            }
+            match branches.iter_mut().find(|nodes| nodes[0].branch == node.branch) {
+                Some(nodes) => nodes.push(node),
+                None => branches.push(vec![node])
+            }
+        }
+
+        let room = self.max_nodes.saturating_sub(self.table[i].len());
+        let mut quotas = vec![0; branches.len()];
+        let mut num_selected = 0;
+        while num_selected < room && quotas.iter().zip(branches.iter()).any(|(quota, nodes)| *quota < nodes.len()) {
+            for (quota, nodes) in quotas.iter_mut().zip(branches.iter()) {
+                if *quota < nodes.len() && num_selected < room {
+                    *quota += 1;
+                    num_selected += 1;
+                }
+            }
+        }
+
+        let mut num_added = 0;
+        for (quota, nodes) in quotas.into_iter().zip(branches) {
+            let len = nodes.len();
+            let picks: Vec<usize> = (0..quota).map(|k| k * len / quota).collect();
+            for (j, node) in nodes.into_iter().enumerate() {
+                if picks.contains(&j) && !self.is_duplicate(i, &node) {
+                    self.table[i].push(node);
+                    num_added += 1;
+                }
+            }
+        }
+        num_added
+    }
+
+    // Spreads candidates of a redundant arm over the self-motion manifold through ik, which reaches
+    // point i, instead of relying on more random restarts or redundancy samples landing on other
+    // parts of it
+    fn add_self_motion_candidates(&mut self, i: usize, ik: &Array1<f64>) {
+        if self.mode != TrackingMode::Pose || self.robot.arm_num_dofs <= 6 || self.table[i].len() >= self.max_nodes {
+            return;
+        }
+        let (pos, quat) = self.goal(i);
+        let samples = self.robot.sample_self_motion(ik, pos, quat, self.null_space_samples, self.null_space_spacing);
+        for sample in samples {
+            if self.table[i].len() >= self.max_nodes {
+                break;
+            }
+            if self.robot.check_collision(&sample) {
+                continue;
+            }
+            let node = self.new_node(sample);
+            if !self.is_duplicate(i, &node) {
+                self.table[i].push(node);
+            }
+        }
+    }
+
+    fn new_node(&self, ik: Array1<f64>) -> Node {
+        let mut node = Node::new(ik);
+        node.branch = self.robot.branch_label(&node.ik);
+        node
+    }
+
+    // Whether a node of point i on the same branch is within the DBSCAN tolerance of node
+    fn is_duplicate(&self, i: usize, node: &Node) -> bool {
+        self.table[i].iter().any(|other| other.branch == node.branch && (&other.ik - &node.ik).mapv(|d| d * d).sum().sqrt() < 0.01)
+    }
+
+    fn try_to_track(&mut self, i: usize) -> (bool, Array1<f64>) {
+        let (pos, quat) = self.goal(i);
+        let (found_ik, ik, _) = match self.mode {
+            TrackingMode::Pose => self.robot.try_to_track(pos, quat),
+            TrackingMode::LookAt => self.robot.try_to_track_look_at(pos)
+        };
+        (found_ik, ik)
+    }
+
     fn sample_candidates(&mut self) {
 
         let n = self.trajectory.len();
@@ -177,42 +346,66 @@ impl IKLink {
 
             assert!(clusters.shape()[0] == tmp_ik_table[i].len());
 
-            let mut labels = vec![false; clusters.shape()[0]];
+            // one node per cluster and branch, so a cluster that straddles a branch boundary keeps
+            // a node on each side
+            let mut kept: HashSet<(usize, BranchLabel)> = HashSet::new();
 
             for j in 0..clusters.shape()[0] {
+                let node = self.new_node(tmp_ik_table[i][j].clone());
                 match clusters[j] {
                     Some(cluster_idx) => {
-                        if !labels[cluster_idx] {
-                            labels[cluster_idx] = true;
-                            let node = Node::new(tmp_ik_table[i][j].clone());
+                        if kept.insert((cluster_idx, node.branch.clone())) {
                             self.table[i].push(node);
                         }
                     },
                     None => {
-                        let node = Node::new(tmp_ik_table[i][j].clone());
                         self.table[i].push(node);
                     }
                 }
             }
 
+            // analytic IK enumerates the IK branches, so random restarts are only needed without it
+            let num_analytic = if self.mode == TrackingMode::Pose { self.add_analytic_candidates(i) } else { 0 };
+
+            // the redundancy samples of analytic IK are sparse, so the self-motion manifold is also
+            // sampled through one analytic solution of each branch
+            let mut seeds: Vec<(BranchLabel, Array1<f64>)> = Vec::new();
+            for node in &self.table[i][self.table[i].len() - num_analytic..] {
+                if !seeds.iter().any(|(branch, _)| *branch == node.branch) {
+                    seeds.push((node.branch.clone(), node.ik.clone()));
+                }
+            }
+            for (_, ik) in seeds {
+                self.add_self_motion_candidates(i, &ik);
+            }
+
             // random sampling
-            while self.table[i].len() < 200 {
-                let (found_ik, ik) = self.robot.try_to_reach(self.trajectory[i].1, self.trajectory[i].2);
-                if !found_ik {
+            let mut num_restarts = 0;
+            while num_analytic == 0 && self.table[i].len() < self.max_nodes && num_restarts < self.max_restarts {
+                num_restarts += 1;
+                let (found_ik, ik) = self.try_to_reach(i);
+                if !found_ik || self.robot.check_collision(&ik) {
                     continue;
                 }
-                let node = Node::new(ik);
+                let node = self.new_node(ik.clone());
                 self.table[i].push(node);
+                self.add_self_motion_candidates(i, &ik);
+            }
+            if self.table[i].is_empty() {
+                println!("Warning: no collision free IK solution found for point {} after {} random restarts", i, num_restarts);
             }
 
             // greedy propagation
             if i < n-1 {
                 for j in 0..self.table[i].len() {
                     self.robot.ik_solver.reset(self.table[i][j].ik.to_vec());
-                    let (found_ik, ik) = self.robot.try_to_track(self.trajectory[i+1].1, self.trajectory[i+1].2);
+                    let (found_ik, ik) = self.try_to_track(i+1);
                     if !found_ik {
                         break;
                     }
+                    if self.robot.check_collision(&ik) {
+                        continue;
+                    }
                     tmp_ik_table[i+1].push(Array1::from(ik));
                 }
             }
@@ -221,19 +414,45 @@ impl IKLink {
     }
 
     pub fn new(robot_name: &str, traj: Vec<(f64, Vector3<f64>, UnitQuaternion<f64>)>) -> Self {
+        Self::with_objectives(robot_name, traj, &RelaxedIK::default_objective_masters)
+    }
+
+    pub fn with_objectives(robot_name: &str, traj: Vec<(f64, Vector3<f64>, UnitQuaternion<f64>)>, objective_master_factory: &ObjectiveMasterFactory) -> Self {
         
-        let robot = Robot::new(robot_name);
+        let robot = Robot::with_objectives(robot_name, objective_master_factory);
 
         let table = vec![];
 
         IKLink {
             robot,
             trajectory:  traj.clone(),
+            workpiece_pose: Isometry3::identity(),
+            mode: TrackingMode::Pose,
+            max_continuations: 2,
+            max_restarts: 2000,
+            max_nodes: 200,
+            null_space_samples: 10,
+            null_space_spacing: 0.2,
             table,
             rng: rand::thread_rng(),
         }
     }
 
+    // Tracks a list of (time, target point) with the look-at objectives and the look_at settings of the robot
+    pub fn look_at(robot_name: &str, targets: Vec<(f64, Vector3<f64>)>) -> Self {
+        let traj = targets.iter().map(|(t, p)| (*t, *p, UnitQuaternion::identity())).collect();
+        let mut iklink = Self::with_objectives(robot_name, traj, &RelaxedIK::look_at_objective_masters);
+        iklink.mode = TrackingMode::LookAt;
+        iklink
+    }
+
+    // Random restarts run the standard IK solver and tracking runs the relaxed IK solver, so this
+    // only changes the restarts. SolverKind::DampedLeastSquares ignores all objectives but the pose
+    // goals and should not be used in look-at mode.
+    pub fn set_restart_solver(&mut self, kind: SolverKind) {
+        self.robot.ik_solver.set_standard_ik_solver(kind);
+    }
+
     pub fn solve(&mut self ) -> Motion{
 
         self.sample_candidates();
@@ -2,4 +2,5 @@ pub mod utils_rust;
 pub mod spacetime;
 pub mod groove;
 pub mod relaxed_ik;
-pub mod iklink;
\ No newline at end of file
+pub mod iklink;
+pub mod relaxed_ik_wrapper;
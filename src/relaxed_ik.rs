@@ -1,62 +1,141 @@
 use crate::groove::vars::RelaxedIKVars;
-use crate::groove::groove::OptimizationEngineOpen;
+use crate::groove::groove::{IKSolver, SolverOptions, SolveResult, ExitReason};
+use crate::groove::solvers::SolverKind;
 use crate::groove::objective_master::ObjectiveMaster;
+use crate::utils_rust::yaml_utils::ObjectiveSpec;
 use crate::utils_rust::file_utils::{*};
 use crate::utils_rust::transformations::{*};
-use std::os::raw::{c_double, c_int};
 use ndarray::Array1;
 
 
-#[repr(C)]
-pub struct Opt {
-    pub data: *const c_double,
-    pub length: c_int,
-}
+pub type ObjectiveMasterFactory = dyn Fn(&RelaxedIKVars) -> (ObjectiveMaster, ObjectiveMaster);
 
 pub struct RelaxedIK {
     pub vars: RelaxedIKVars,
     pub om_relaxedik: ObjectiveMaster,
     pub om_standardik: ObjectiveMaster,
-    pub groove: OptimizationEngineOpen
+    // solve(true) runs solver_relaxedik on om_relaxedik and solve(false) runs solver_standardik on om_standardik
+    pub solver_relaxedik: Box<dyn IKSolver + Send>,
+    pub solver_standardik: Box<dyn IKSolver + Send>,
 }
 
 impl RelaxedIK {
     pub fn load_settings( path_to_setting: &str) -> Self {
+        Self::load_settings_with_objectives(path_to_setting, &Self::default_objective_masters)
+    }
+
+    // The factory returns the objective masters used by solve(true) and solve(false), in that order
+    pub fn load_settings_with_objectives(path_to_setting: &str, objective_master_factory: &ObjectiveMasterFactory) -> Self {
         println!("RelaxedIK is using below setting file {}", path_to_setting);
 
         let vars = RelaxedIKVars::from_local_settings(path_to_setting);        
-        let om_relaxedik: ObjectiveMaster = ObjectiveMaster::relaxed_ik(&vars.robot.chain_indices);
-        let om_standardik: ObjectiveMaster = ObjectiveMaster::standard_ik(&vars.robot.chain_indices);
+        let (om_relaxedik, om_standardik) = objective_master_factory(&vars);
+
+        let solver_relaxedik = SolverKind::Panoc.build(vars.robot.num_dofs, SolverOptions::default());
+        let solver_standardik = SolverKind::Panoc.build(vars.robot.num_dofs, SolverOptions::default());
 
-        let groove = OptimizationEngineOpen::new(vars.robot.num_dofs.clone());
+        Self{vars, om_relaxedik, om_standardik, solver_relaxedik, solver_standardik}
+    }
+
+    // Objectives listed in the settings file, or the default ones
+    pub fn default_objective_masters(vars: &RelaxedIKVars) -> (ObjectiveMaster, ObjectiveMaster) {
+        let om_relaxedik = ObjectiveMaster::from_specs(&vars.relaxed_ik_objectives, &vars.robot.chain_indices, !vars.env_collision.obstacles.is_empty());
+        let om_standardik = ObjectiveMaster::from_specs(&vars.standard_ik_objectives, &vars.robot.chain_indices, false);
+        (om_relaxedik, om_standardik)
+    }
 
-        Self{vars, om_relaxedik, om_standardik, groove}
+    pub fn look_at_objective_masters(vars: &RelaxedIKVars) -> (ObjectiveMaster, ObjectiveMaster) {
+        let om_relaxedik = ObjectiveMaster::from_specs(&ObjectiveSpec::look_at_relaxed_ik(&vars.objective_options), &vars.robot.chain_indices, !vars.env_collision.obstacles.is_empty());
+        let om_standardik = ObjectiveMaster::from_specs(&ObjectiveSpec::look_at_standard_ik(), &vars.robot.chain_indices, false);
+        (om_relaxedik, om_standardik)
     }
 
     pub fn reset(&mut self, x: Vec<f64>) {
+        // the objective masters and the PANOC cache carry nothing over between solves, so they are
+        // kept, along with weights and solver options set at runtime
         self.vars.reset( x.clone());
-        self.om_relaxedik = ObjectiveMaster::relaxed_ik(&self.vars.robot.chain_indices);
-        self.om_standardik = ObjectiveMaster::standard_ik(&self.vars.robot.chain_indices);
-        self.groove = OptimizationEngineOpen::new(self.vars.robot.num_dofs.clone());
     }
 
-    pub fn solve(&mut self, constrain_velocity: bool) -> Array1<f64> {
+    // Objectives are looked up by the kind they were configured with (e.g. ee_rotation_dofs) in
+    // both the relaxed and the standard objective master.
+    pub fn objective_names(&self) -> Vec<String> {
+        let mut out = self.om_relaxedik.objective_names();
+        for name in self.om_standardik.objective_names() {
+            if !out.contains(&name) {
+                out.push(name);
+            }
+        }
+        out
+    }
+
+    pub fn get_objective_weight(&self, name: &str) -> Option<f64> {
+        self.om_relaxedik.get_weight(name).or_else(|| self.om_standardik.get_weight(name))
+    }
+
+    pub fn set_objective_weight(&mut self, name: &str, weight: f64) -> bool {
+        let found_relaxed = self.om_relaxedik.set_weight(name, weight);
+        let found_standard = self.om_standardik.set_weight(name, weight);
+        if !found_relaxed && !found_standard {
+            println!("Warning: no objective named {}", name);
+        }
+        found_relaxed || found_standard
+    }
+
+    pub fn is_objective_enabled(&self, name: &str) -> Option<bool> {
+        self.om_relaxedik.is_enabled(name).or_else(|| self.om_standardik.is_enabled(name))
+    }
+
+    pub fn set_objective_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let found_relaxed = self.om_relaxedik.set_enabled(name, enabled);
+        let found_standard = self.om_standardik.set_enabled(name, enabled);
+        if !found_relaxed && !found_standard {
+            println!("Warning: no objective named {}", name);
+        }
+        found_relaxed || found_standard
+    }
+
+    pub fn solver_options(&self) -> SolverOptions {
+        self.solver_relaxedik.options()
+    }
+
+    // Applies to both solvers
+    pub fn set_solver_options(&mut self, options: SolverOptions) {
+        self.solver_relaxedik.set_options(options);
+        self.solver_standardik.set_options(options);
+    }
+
+    // The new solver keeps the current solver options
+    pub fn set_relaxed_ik_solver(&mut self, kind: SolverKind) {
+        self.solver_relaxedik = kind.build(self.vars.robot.num_dofs, self.solver_relaxedik.options());
+    }
+
+    pub fn set_standard_ik_solver(&mut self, kind: SolverKind) {
+        self.solver_standardik = kind.build(self.vars.robot.num_dofs, self.solver_standardik.options());
+    }
+
+    // Returns the solution and how the optimizer finished. If the solution is not finite the
+    // previous solution is returned and the exit reason is ExitReason::NotFinite.
+    pub fn solve(&mut self, constrain_velocity: bool) -> (Array1<f64>, SolveResult) {
         let mut out_x = self.vars.xopt.clone();
 
-        if constrain_velocity {
-            self.groove.optimize(&mut out_x, &self.vars, &self.om_relaxedik, 100);
+        let mut result = if constrain_velocity {
+            let max_iter = self.solver_relaxedik.options().max_iter_relaxed_ik;
+            self.solver_relaxedik.optimize(&mut out_x, &self.vars, &self.om_relaxedik, max_iter)
         } else {
-            self.groove.optimize(&mut out_x, &self.vars, &self.om_standardik, 1000);
-        }
+            let max_iter = self.solver_standardik.options().max_iter_standard_ik;
+            self.solver_standardik.optimize(&mut out_x, &self.vars, &self.om_standardik, max_iter)
+        };
         // let frames = self.vars.robot.get_frames_immutable(&out_x);
 
         for i in 0..out_x.len() {
             if out_x[i].is_nan() {
                 // println!("No valid solution found! Returning previous solution: {:?}. End effector position goals: {:?}", self.vars.xopt, self.vars.goal_positions);
-                return Array1::from(self.vars.xopt.clone());
+                result.converged = false;
+                result.exit_reason = ExitReason::NotFinite;
+                return (Array1::from(self.vars.xopt.clone()), result);
             }
         }
         self.vars.update(out_x.clone());  
-        Array1::from(out_x)
+        (Array1::from(out_x), result)
     }
 }
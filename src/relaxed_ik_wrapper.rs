@@ -0,0 +1,266 @@
+// C API of RelaxedIK and IKLink, see include/relaxed_ik_core.h
+//
+// Memory ownership:
+// - relaxed_ik_new and iklink_new return handles owned by the caller, to be released with
+//   relaxed_ik_free and iklink_free. A handle may be used from one thread at a time.
+// - Input arrays and strings are borrowed for the duration of the call only.
+// - Opt buffers and strings returned by these functions are allocated by Rust and owned by the
+//   caller, who releases them with relaxed_ik_free_opt and relaxed_ik_free_string. They must
+//   not be released with free().
+// - Errors never unwind into C. Functions returning a handle or a string return NULL on
+//   failure, functions returning an Opt return one with a NULL data pointer and a length of
+//   0, and functions returning an int return -1. The error is printed.
+// - Quaternions are laid out as (x, y, z, w).
+//
+// These rules are the safety contract of all functions below.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::relaxed_ik::RelaxedIK;
+use crate::groove::groove::{SolveResult, ExitReason};
+use crate::iklink::IKLink;
+use nalgebra::{Vector3, Vector6, UnitQuaternion, Quaternion};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+/// An array of f64
+#[repr(C)]
+pub struct Opt {
+    pub data: *const c_double,
+    pub length: c_int,
+}
+
+impl Opt {
+    fn from_vec(x: Vec<f64>) -> Self {
+        // a boxed slice has no spare capacity, so relaxed_ik_free_opt can rebuild it from the length
+        let length = x.len() as c_int;
+        let data = Box::into_raw(x.into_boxed_slice()) as *const c_double;
+        Opt{data, length}
+    }
+
+    fn null() -> Self {
+        Opt{data: ptr::null(), length: 0}
+    }
+}
+
+/// How a solve finished, see ExitReason
+#[repr(C)]
+pub enum SolveExitReason {
+    Converged,
+    MaxIterations,
+    OutOfTime,
+    /// the solver stopped making progress short of the tolerance
+    Stalled,
+    /// the solution was not finite and the previous solution was returned
+    NotFinite,
+}
+
+/// Outcome of a solve, see SolveResult
+#[repr(C)]
+pub struct SolveStatus {
+    pub converged: bool,
+    pub exit_reason: SolveExitReason,
+    pub iterations: c_int,
+    /// cost at the returned solution, NaN if the solver failed
+    pub cost: c_double,
+}
+
+impl From<&SolveResult> for SolveStatus {
+    fn from(result: &SolveResult) -> Self {
+        let exit_reason = match result.exit_reason {
+            ExitReason::Converged => SolveExitReason::Converged,
+            ExitReason::MaxIterations => SolveExitReason::MaxIterations,
+            ExitReason::OutOfTime => SolveExitReason::OutOfTime,
+            ExitReason::Stalled => SolveExitReason::Stalled,
+            ExitReason::NotFinite => SolveExitReason::NotFinite,
+        };
+        SolveStatus{converged: result.converged, exit_reason, iterations: result.iterations as c_int, cost: result.cost}
+    }
+}
+
+// Runs f and turns a panic into the error value of the caller
+fn catch<T>(on_error: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_error)
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> &'a str {
+    assert!(!s.is_null(), "Null string");
+    CStr::from_ptr(s).to_str().expect("Not a valid UTF-8 string")
+}
+
+unsafe fn to_slice<'a>(data: *const c_double, length: usize) -> &'a [f64] {
+    if length == 0 {
+        return &[];
+    }
+    assert!(!data.is_null(), "Null array");
+    slice::from_raw_parts(data, length)
+}
+
+fn to_quat(q: &[f64]) -> UnitQuaternion<f64> {
+    UnitQuaternion::from_quaternion(Quaternion::new(q[3], q[0], q[1], q[2]))
+}
+
+/// Loads a solver from a settings file, e.g. configs/settings.yaml. Returns NULL if the settings
+/// cannot be loaded.
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_new(path_to_setting: *const c_char) -> *mut RelaxedIK {
+    catch(ptr::null_mut(), || {
+        let relaxed_ik = RelaxedIK::load_settings(to_str(path_to_setting));
+        Box::into_raw(Box::new(relaxed_ik))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_free(ptr: *mut RelaxedIK) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_num_dofs(ptr: *const RelaxedIK) -> c_int {
+    catch(-1, || {
+        assert!(!ptr.is_null(), "Null solver");
+        (*ptr).vars.robot.num_dofs as c_int
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_num_chains(ptr: *const RelaxedIK) -> c_int {
+    catch(-1, || {
+        assert!(!ptr.is_null(), "Null solver");
+        (*ptr).vars.robot.num_chains as c_int
+    })
+}
+
+/// Name of the joint of a dof, in the order of the solutions. Release with relaxed_ik_free_string.
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_joint_name(ptr: *const RelaxedIK, dof: c_int) -> *mut c_char {
+    catch(ptr::null_mut(), || {
+        assert!(!ptr.is_null(), "Null solver");
+        let joint_names = (*ptr).vars.robot.joint_names();
+        assert!(dof >= 0 && (dof as usize) < joint_names.len(), "Invalid dof {}, the robot has {}", dof, joint_names.len());
+        CString::new(joint_names[dof as usize].clone()).unwrap().into_raw()
+    })
+}
+
+/// Sets the end effector goals of all chains in the world frame: num_chains positions (x, y, z),
+/// num_chains quaternions (x, y, z, w) and, unless tolerances is NULL, num_chains tolerances
+/// (x, y, z, rx, ry, rz). Returns 0, or -1 if num_chains does not match the robot.
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_set_goals(ptr: *mut RelaxedIK, positions: *const c_double, quats: *const c_double,
+                                              tolerances: *const c_double, num_chains: c_int) -> c_int {
+    catch(-1, || {
+        assert!(!ptr.is_null(), "Null solver");
+        let relaxed_ik = &mut *ptr;
+        let n = relaxed_ik.vars.robot.num_chains;
+        assert!(num_chains as usize == n, "Invalid number of chains {}, expected {}", num_chains, n);
+
+        let positions = to_slice(positions, 3 * n);
+        let quats = to_slice(quats, 4 * n);
+        for i in 0..n {
+            relaxed_ik.vars.goal_positions[i] = Vector3::new(positions[3*i], positions[3*i+1], positions[3*i+2]);
+            relaxed_ik.vars.goal_quats[i] = to_quat(&quats[4*i..4*i+4]);
+        }
+        if !tolerances.is_null() {
+            let tolerances = to_slice(tolerances, 6 * n);
+            for i in 0..n {
+                relaxed_ik.vars.tolerances[i] = Vector6::from_column_slice(&tolerances[6*i..6*i+6]);
+            }
+        }
+        0
+    })
+}
+
+/// Solves for the current goals, with the velocity limits if constrain_velocity is nonzero, and
+/// returns the joint values. Release with relaxed_ik_free_opt. Unless status is NULL, it is set to
+/// how the solve finished; a solve that is not converged may return the previous solution. status
+/// is left unchanged if the returned Opt is empty.
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_solve(ptr: *mut RelaxedIK, constrain_velocity: c_int, status: *mut SolveStatus) -> Opt {
+    catch(Opt::null(), || {
+        assert!(!ptr.is_null(), "Null solver");
+        let (x, result) = (*ptr).solve(constrain_velocity != 0);
+        if !status.is_null() {
+            *status = SolveStatus::from(&result);
+        }
+        Opt::from_vec(x.to_vec())
+    })
+}
+
+/// Resets the solver to a configuration of num_dofs joint values. Returns 0, or -1 if length
+/// does not match the robot.
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_reset(ptr: *mut RelaxedIK, x: *const c_double, length: c_int) -> c_int {
+    catch(-1, || {
+        assert!(!ptr.is_null(), "Null solver");
+        assert!(length >= 0, "Invalid length {}", length);
+        (*ptr).reset(to_slice(x, length as usize).to_vec());
+        0
+    })
+}
+
+/// Loads the robot configs/example_settings/<robot_name>.yaml, relative to the working
+/// directory. Returns NULL if the robot cannot be loaded.
+#[no_mangle]
+pub unsafe extern "C" fn iklink_new(robot_name: *const c_char) -> *mut IKLink {
+    catch(ptr::null_mut(), || {
+        let iklink = IKLink::new(to_str(robot_name), vec![]);
+        Box::into_raw(Box::new(iklink))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn iklink_free(ptr: *mut IKLink) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(ptr));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn iklink_num_dofs(ptr: *const IKLink) -> c_int {
+    catch(-1, || {
+        assert!(!ptr.is_null(), "Null IKLink");
+        (*ptr).robot.ik_solver.vars.robot.num_dofs as c_int
+    })
+}
+
+/// Tracks num_poses end effector poses (t, x, y, z, qx, qy, qz, qw), in the layout of the
+/// trajectories in input_trajectories. Returns the motion as num_poses rows of
+/// (t, joint values), i.e. num_poses * (1 + num_dofs) values. Release with relaxed_ik_free_opt.
+#[no_mangle]
+pub unsafe extern "C" fn iklink_solve(ptr: *mut IKLink, poses: *const c_double, num_poses: c_int) -> Opt {
+    catch(Opt::null(), || {
+        assert!(!ptr.is_null(), "Null IKLink");
+        assert!(num_poses > 0, "Invalid number of poses {}", num_poses);
+        let iklink = &mut *ptr;
+        let poses = to_slice(poses, 8 * num_poses as usize);
+
+        iklink.trajectory = poses.chunks(8).map(|p| (p[0], Vector3::new(p[1], p[2], p[3]), to_quat(&p[4..8]))).collect();
+        iklink.table.clear();
+        let motion = iklink.solve();
+
+        let mut out = Vec::with_capacity(motion.data.len() * (1 + motion.joint_names.len()));
+        for (t, x) in &motion.data {
+            out.push(*t);
+            out.extend(x.iter());
+        }
+        Opt::from_vec(out)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_free_opt(opt: Opt) {
+    if !opt.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(opt.data as *mut c_double, opt.length as usize)));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn relaxed_ik_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
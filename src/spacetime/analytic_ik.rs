@@ -0,0 +1,428 @@
+use nalgebra::{Vector3, Point3, UnitQuaternion, Isometry3, Translation3, Unit};
+use crate::spacetime::robot_kinematics::RobotKinematics;
+use crate::spacetime::branches::{BranchTest, ArmVector};
+use std::f64::consts::PI;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+// Closed-form inverse kinematics on the product of exponentials form of the chain, built from the
+// Paden-Kahan subproblems. The joint axes are read from the URDF at the zero configuration, so
+// any single chain arm with one of the layouts below is supported, whatever its link lengths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArmLayout {
+    // 6 joints: a base joint, three parallel joints and two intersecting wrist joints, e.g. UR5
+    ThreeParallel,
+    // 7 joints: a spherical shoulder, an elbow and a spherical wrist, e.g. iiwa. The redundancy
+    // parameter is the angle of the elbow around the shoulder-wrist line.
+    SphericalShoulderWrist,
+    // 7 joints: a spherical shoulder, an elbow and intersecting joints 5 and 6, e.g. Panda. The
+    // redundancy parameter is the value of joint 7.
+    OffsetWrist,
+}
+
+// axes closer than this (in meters) are taken to intersect
+const INTERSECTION_TOLERANCE: f64 = 5e-3;
+// axes closer than this (in radians) are taken to be parallel
+const PARALLEL_TOLERANCE: f64 = 1e-2;
+// largest position (in meters) plus rotation (in radians) error of a solution of a detected layout
+const ROUND_TRIP_TOLERANCE: f64 = 1e-4;
+const ROUND_TRIP_SAMPLES: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct AnalyticIK {
+    pub layout: ArmLayout,
+    // values of the redundancy parameter that solve() samples for 7 joint arms
+    pub redundancy_samples: usize,
+    // unit axis and a point on the axis of each joint at the zero configuration, in the base frame
+    axes: Vec<Vector3<f64>>,
+    points: Vec<Vector3<f64>>,
+    // end effector pose at the zero configuration
+    home: Isometry3<f64>,
+    // shoulder point of 7 joint arms and wrist point, where the wrist axes intersect
+    shoulder: Vector3<f64>,
+    wrist: Vector3<f64>,
+    num_dofs: usize,
+    dof_indices: Vec<usize>,
+    lower_limits: Vec<f64>,
+    upper_limits: Vec<f64>,
+}
+
+impl AnalyticIK {
+    // Returns None if the robot does not have one of the supported layouts
+    pub fn from_robot(robot: &RobotKinematics) -> Option<Self> {
+        if robot.num_chains != 1 {
+            return None;
+        }
+        let arm = &robot.arms[0];
+        if arm.joint_types.iter().any(|joint_type| joint_type == "prismatic") {
+            return None;
+        }
+        // the closed-form solutions need every joint of the arm to be a dof of its own
+        let dof_indices = &robot.chain_indices[0];
+        if robot.chain_mimic[0].iter().any(|&mimic| mimic != (1.0, 0.0)) || (1..dof_indices.len()).any(|j| dof_indices[..j].contains(&dof_indices[j])) {
+            return None;
+        }
+        let zeros = vec![0.0; arm.num_dof];
+        let (axes, points) = arm.get_joint_axes_immutable(&zeros);
+        let axes: Vec<Vector3<f64>> = axes.iter().map(|axis| axis.normalize()).collect();
+        let (home_position, home_quat) = arm.get_ee_pos_and_quat_immutable(&zeros);
+        let home = Isometry3::from_parts(Translation3::from(home_position), home_quat);
+
+        let dof_indices = robot.chain_indices[0].clone();
+        let lower_limits = dof_indices.iter().map(|&i| robot.joints[i].lower_limit).collect();
+        let upper_limits = dof_indices.iter().map(|&i| robot.joints[i].upper_limit).collect();
+
+        let intersection = |a: usize, b: usize| axis_intersection(&points[a], &axes[a], &points[b], &axes[b]);
+        let on_axis = |x: &Vector3<f64>, a: usize| (x - points[a]).cross(&axes[a]).norm() < INTERSECTION_TOLERANCE;
+        let parallel = |a: usize, b: usize| axes[a].cross(&axes[b]).norm() < PARALLEL_TOLERANCE;
+
+        let (layout, shoulder, wrist) = match axes.len() {
+            6 => {
+                let wrist = intersection(4, 5)?;
+                if !(parallel(1, 2) && parallel(2, 3)) || parallel(0, 1) {
+                    return None;
+                }
+                (ArmLayout::ThreeParallel, points[0], wrist)
+            },
+            7 => {
+                let shoulder = intersection(0, 1)?;
+                let wrist = intersection(4, 5)?;
+                if !on_axis(&shoulder, 2) || parallel(0, 1) || parallel(1, 2) || parallel(4, 5) {
+                    return None;
+                }
+                if on_axis(&wrist, 6) {
+                    (ArmLayout::SphericalShoulderWrist, shoulder, wrist)
+                } else {
+                    (ArmLayout::OffsetWrist, shoulder, wrist)
+                }
+            },
+            _ => return None
+        };
+
+        let analytic_ik = Self{layout, redundancy_samples: 24, axes, points, home, shoulder, wrist, num_dofs: robot.num_dofs,
+            dof_indices, lower_limits, upper_limits};
+
+        // the tolerances above also accept axes that are a few millimeters apart, e.g. Jaco, whose
+        // solutions then miss the pose
+        let (_, num_missed, max_error) = analytic_ik.check_round_trips(robot, ROUND_TRIP_SAMPLES, &mut StdRng::seed_from_u64(0));
+        if num_missed > 0 {
+            println!("Warning: analytic IK solutions for the {:?} layout miss the pose by up to {:.1e}, analytic IK is not used", layout, max_error);
+            return None;
+        }
+        Some(analytic_ik)
+    }
+
+    // Solves for the end effector poses of random configurations within the joint limits and
+    // returns the number of solutions, how many of them miss their pose and the largest error
+    pub fn check_round_trips(&self, robot: &RobotKinematics, num_samples: usize, rng: &mut impl Rng) -> (usize, usize, f64) {
+        let (mut num_solutions, mut num_missed, mut max_error) = (0, 0, 0.0);
+        for _ in 0..num_samples {
+            let x: Vec<f64> = robot.joints.iter().map(|joint| rng.gen_range(joint.lower_limit.max(-PI)..=joint.upper_limit.min(PI))).collect();
+            let (pos, quat) = robot.get_ee_pos_and_quat_immutable(&x)[0];
+            for solution in self.solve(pos, quat) {
+                let (ee_pos, ee_quat) = robot.get_ee_pos_and_quat_immutable(&solution)[0];
+                let error = (ee_pos - pos).norm() + (ee_quat * quat.inverse()).angle();
+                num_solutions += 1;
+                if error > ROUND_TRIP_TOLERANCE {
+                    num_missed += 1;
+                }
+                max_error = f64::max(max_error, error);
+            }
+        }
+        (num_solutions, num_missed, max_error)
+    }
+
+    // All solutions within the joint limits for an end effector pose in the base frame. For 7 joint
+    // arms the redundancy parameter is sampled evenly over its range.
+    pub fn solve(&self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> Vec<Vec<f64>> {
+        match self.layout {
+            ArmLayout::ThreeParallel => self.solve_with_redundancy(pos, quat, 0.0),
+            ArmLayout::SphericalShoulderWrist | ArmLayout::OffsetWrist => {
+                let (lower, upper) = self.redundancy_range();
+                let mut solutions = Vec::new();
+                for i in 0..self.redundancy_samples {
+                    let param = lower + (upper - lower) * (i as f64 + 0.5) / self.redundancy_samples as f64;
+                    solutions.extend(self.solve_with_redundancy(pos, quat, param));
+                }
+                solutions
+            }
+        }
+    }
+
+    // Geometric tests for the shoulder, elbow and wrist branches that the closed form tells apart
+    pub fn branch_tests(&self) -> Vec<(String, BranchTest)> {
+        let triple = |a, b, c| BranchTest::TripleProduct(a, b, c);
+        let (shoulder, elbow, wrist) = match self.layout {
+            ArmLayout::ThreeParallel => (
+                // the wrist in front of or behind the base joint
+                triple(ArmVector::Axis(0), ArmVector::Axis(1), ArmVector::Link(0, 4)),
+                // the elbow on either side of the line from the shoulder to the wrist
+                triple(ArmVector::Link(1, 2), ArmVector::Link(2, 3), ArmVector::Axis(1)),
+                triple(ArmVector::Axis(3), ArmVector::Axis(5), ArmVector::Axis(4))),
+            // the sign of the middle joint of the shoulder, elbow and wrist
+            ArmLayout::SphericalShoulderWrist => (
+                triple(ArmVector::Axis(0), ArmVector::Axis(2), ArmVector::Axis(1)),
+                triple(ArmVector::Axis(2), ArmVector::Axis(4), ArmVector::Axis(3)),
+                triple(ArmVector::Axis(4), ArmVector::Axis(6), ArmVector::Axis(5))),
+            // the wrist flag tells apart the two arm angles that line up joints 5 and 6 with the
+            // last axis, by the side of the shoulder-wrist line the axis of joint 5 turns to
+            ArmLayout::OffsetWrist => (
+                triple(ArmVector::Axis(0), ArmVector::Axis(2), ArmVector::Axis(1)),
+                triple(ArmVector::Axis(2), ArmVector::Axis(4), ArmVector::Axis(3)),
+                triple(ArmVector::Axis(4), ArmVector::Axis(5), ArmVector::Link(1, 4))),
+        };
+        vec![("shoulder".to_string(), shoulder), ("elbow".to_string(), elbow), ("wrist".to_string(), wrist)]
+    }
+
+    pub fn redundancy_range(&self) -> (f64, f64) {
+        match self.layout {
+            ArmLayout::ThreeParallel => (0.0, 0.0),
+            ArmLayout::SphericalShoulderWrist => (-PI, PI),
+            ArmLayout::OffsetWrist => (self.lower_limits[6].max(-PI), self.upper_limits[6].min(PI)),
+        }
+    }
+
+    // Solutions within the joint limits for one value of the redundancy parameter, which is
+    // ignored by 6 joint arms
+    pub fn solve_with_redundancy(&self, pos: Vector3<f64>, quat: UnitQuaternion<f64>, param: f64) -> Vec<Vec<f64>> {
+        let goal = Isometry3::from_parts(Translation3::from(pos), quat) * self.home.inverse();
+        let branches = match self.layout {
+            ArmLayout::ThreeParallel => self.solve_three_parallel(&goal),
+            ArmLayout::SphericalShoulderWrist => self.solve_spherical_wrist(&goal, param),
+            ArmLayout::OffsetWrist => self.solve_offset_wrist(&goal, param),
+        };
+
+        let mut solutions: Vec<Vec<f64>> = Vec::new();
+        for branch in branches {
+            let q = match self.wrap_to_limits(&branch) {
+                Some(q) => q,
+                None => continue
+            };
+            if solutions.iter().any(|s| s.iter().zip(q.iter()).all(|(a, b)| (a - b).abs() < 1e-9)) {
+                continue;
+            }
+            solutions.push(q);
+        }
+
+        solutions.iter().map(|q| {
+            let mut x = vec![0.0; self.num_dofs];
+            for (j, &i) in self.dof_indices.iter().enumerate() {
+                x[i] = q[j];
+            }
+            x
+        }).collect()
+    }
+
+    // Moves every joint value into its limits by multiples of 2 pi, preferring values in (-pi, pi]
+    fn wrap_to_limits(&self, q: &[f64]) -> Option<Vec<f64>> {
+        let mut out = Vec::with_capacity(q.len());
+        for (i, &value) in q.iter().enumerate() {
+            if !value.is_finite() {
+                return None;
+            }
+            let value = value - 2.0 * PI * ((value - PI) / (2.0 * PI)).ceil();
+            let wrapped = [value, value - 2.0 * PI, value + 2.0 * PI].iter().copied()
+                .find(|v| *v >= self.lower_limits[i] && *v <= self.upper_limits[i])?;
+            out.push(wrapped);
+        }
+        Some(out)
+    }
+
+    fn rotation(&self, joint: usize, angle: f64) -> UnitQuaternion<f64> {
+        UnitQuaternion::from_axis_angle(&Unit::new_unchecked(self.axes[joint]), angle)
+    }
+
+    // Rigid motion of joint rotating by angle, in the base frame
+    fn screw(&self, joint: usize, angle: f64) -> Isometry3<f64> {
+        let rotation = self.rotation(joint, angle);
+        Isometry3::from_parts(Translation3::from(self.points[joint] - rotation * self.points[joint]), rotation)
+    }
+
+    // goal is the pose of the end effector times the inverse of its pose at the zero configuration,
+    // i.e. the product of the screw motions of all joints
+    fn solve_three_parallel(&self, goal: &Isometry3<f64>) -> Vec<Vec<f64>> {
+        let mut solutions = Vec::new();
+        let axes = &self.axes;
+        let points = &self.points;
+        let wrist = (goal * Point3::from(self.wrist)).coords;
+        let goal_rotation = goal.rotation;
+        let sign3 = axes[2].dot(&axes[1]).signum();
+        let sign4 = axes[3].dot(&axes[1]).signum();
+
+        // joints 2 to 4 keep the offset of the wrist along their axes
+        let offset = (self.wrist - points[0]).dot(&axes[1]);
+        for q1 in subproblem4(&axes[0], &(wrist - points[0]), &axes[1], offset) {
+            let rotation1 = self.rotation(0, q1);
+            // joints 2 to 4 keep the angle between the last axis and their axes
+            let d = axes[1].dot(&(rotation1.inverse() * goal_rotation * axes[5]));
+            for q5 in subproblem4(&axes[4], &axes[1], &axes[5], d) {
+                let rotation5 = self.rotation(4, q5);
+                let rotation234_5_6 = rotation1.inverse() * goal_rotation;
+                let q6 = subproblem1(&axes[5], &(rotation234_5_6.inverse() * axes[1]), &(rotation5.inverse() * axes[1]));
+                let rotation234 = rotation234_5_6 * self.rotation(5, q6).inverse() * rotation5.inverse();
+                let v = perpendicular(&axes[1]);
+                let q234 = subproblem1(&axes[1], &v, &(rotation234 * v));
+
+                // the point of joint 4 only moves with joints 2 and 3
+                let motion234 = self.screw(0, q1).inverse() * goal * self.screw(5, q6).inverse() * self.screw(4, q5).inverse();
+                let joint4 = (motion234 * Point3::from(points[3])).coords;
+                for q3 in subproblem3(&axes[2], &points[2], &points[3], &points[1], (joint4 - points[1]).norm()) {
+                    let moved = (self.screw(2, q3) * Point3::from(points[3])).coords;
+                    let q2 = subproblem1(&axes[1], &(moved - points[1]), &(joint4 - points[1]));
+                    let q4 = sign4 * (q234 - q2 - sign3 * q3);
+                    solutions.push(vec![q1, q2, q3, q4, q5, q6]);
+                }
+            }
+        }
+        solutions
+    }
+
+    fn solve_spherical_wrist(&self, goal: &Isometry3<f64>, arm_angle: f64) -> Vec<Vec<f64>> {
+        let mut solutions = Vec::new();
+        let wrist = (goal * Point3::from(self.wrist)).coords;
+        for q4 in self.elbow_angles(&wrist) {
+            let rotation123 = match self.shoulder_rotation(&wrist, q4) {
+                Some((reference, axis)) => UnitQuaternion::from_axis_angle(&axis, arm_angle) * reference,
+                None => continue
+            };
+            for [q1, q2, q3] in euler_angles([&self.axes[0], &self.axes[1], &self.axes[2]], &rotation123) {
+                let rotation_wrist = (rotation123 * self.rotation(3, q4)).inverse() * goal.rotation;
+                for [q5, q6, q7] in euler_angles([&self.axes[4], &self.axes[5], &self.axes[6]], &rotation_wrist) {
+                    solutions.push(vec![q1, q2, q3, q4, q5, q6, q7]);
+                }
+            }
+        }
+        solutions
+    }
+
+    fn solve_offset_wrist(&self, goal: &Isometry3<f64>, q7: f64) -> Vec<Vec<f64>> {
+        let mut solutions = Vec::new();
+        let motion = goal * self.screw(6, q7).inverse();
+        let wrist = (motion * Point3::from(self.wrist)).coords;
+        for q4 in self.elbow_angles(&wrist) {
+            let (reference, axis) = match self.shoulder_rotation(&wrist, q4) {
+                Some(rotation) => rotation,
+                None => continue
+            };
+            // joints 5 and 6 can only reach a last axis at the right angle to the axis of joint 5
+            let b = reference * self.rotation(3, q4) * self.axes[4];
+            let a = motion.rotation * self.axes[5];
+            for arm_angle in subproblem4(&axis, &a, &b, self.axes[4].dot(&self.axes[5])) {
+                let rotation123 = UnitQuaternion::from_axis_angle(&axis, arm_angle) * reference;
+                for [q1, q2, q3] in euler_angles([&self.axes[0], &self.axes[1], &self.axes[2]], &rotation123) {
+                    let rotation56 = (rotation123 * self.rotation(3, q4)).inverse() * motion.rotation;
+                    let q5 = subproblem1(&self.axes[4], &self.axes[5], &(rotation56 * self.axes[5]));
+                    let v = perpendicular(&self.axes[5]);
+                    let q6 = subproblem1(&self.axes[5], &v, &(self.rotation(4, q5).inverse() * rotation56 * v));
+                    solutions.push(vec![q1, q2, q3, q4, q5, q6, q7]);
+                }
+            }
+        }
+        solutions
+    }
+
+    // The elbow sets the distance between the shoulder and the wrist
+    fn elbow_angles(&self, wrist: &Vector3<f64>) -> Vec<f64> {
+        subproblem3(&self.axes[3], &self.points[3], &self.wrist, &self.shoulder, (wrist - self.shoulder).norm())
+    }
+
+    // A rotation of the shoulder that places the wrist, and the shoulder-wrist axis around which
+    // every other such rotation differs from it
+    fn shoulder_rotation(&self, wrist: &Vector3<f64>, q4: f64) -> Option<(UnitQuaternion<f64>, Unit<Vector3<f64>>)> {
+        let from = (self.screw(3, q4) * Point3::from(self.wrist)).coords - self.shoulder;
+        let to = wrist - self.shoulder;
+        let axis = Unit::try_new(to, 1e-9)?;
+        let reference = UnitQuaternion::rotation_between(&from, &to)
+            .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Unit::new_normalize(perpendicular(&from)), PI));
+        Some((reference, axis))
+    }
+}
+
+// Closest point between two axes, if they intersect
+fn axis_intersection(p1: &Vector3<f64>, w1: &Vector3<f64>, p2: &Vector3<f64>, w2: &Vector3<f64>) -> Option<Vector3<f64>> {
+    let n = w1.cross(w2);
+    if n.norm() < PARALLEL_TOLERANCE {
+        return None;
+    }
+    let d = p2 - p1;
+    if d.dot(&n).abs() / n.norm() > INTERSECTION_TOLERANCE {
+        return None;
+    }
+    let t1 = d.cross(w2).dot(&n) / n.norm_squared();
+    let t2 = d.cross(w1).dot(&n) / n.norm_squared();
+    Some((p1 + w1 * t1 + p2 + w2 * t2) * 0.5)
+}
+
+fn perpendicular(w: &Vector3<f64>) -> Vector3<f64> {
+    let other = if w.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+    w.cross(&other).normalize()
+}
+
+// Angle of the rotation about w that takes p to q
+fn subproblem1(w: &Vector3<f64>, p: &Vector3<f64>, q: &Vector3<f64>) -> f64 {
+    let p = p - w * w.dot(p);
+    let q = q - w * w.dot(q);
+    w.dot(&p.cross(&q)).atan2(p.dot(&q))
+}
+
+// Angles of the rotations about w1 and w2 with rot(w1, t1) * rot(w2, t2) * p = q
+fn subproblem2(w1: &Vector3<f64>, w2: &Vector3<f64>, p: &Vector3<f64>, q: &Vector3<f64>) -> Vec<(f64, f64)> {
+    let w12 = w1.dot(w2);
+    let denominator = w12 * w12 - 1.0;
+    let alpha = (w12 * w2.dot(p) - w1.dot(q)) / denominator;
+    let beta = (w12 * w1.dot(q) - w2.dot(p)) / denominator;
+    let n = w1.cross(w2);
+    let gamma_squared = (p.norm_squared() - alpha * alpha - beta * beta - 2.0 * alpha * beta * w12) / n.norm_squared();
+    if gamma_squared < -1e-9 {
+        return vec![];
+    }
+    let gamma = gamma_squared.max(0.0).sqrt();
+    let mut solutions = Vec::new();
+    for sign in &[1.0, -1.0] {
+        let c = w1 * alpha + w2 * beta + n * (sign * gamma);
+        solutions.push((subproblem1(w1, &c, q), subproblem1(w2, p, &c)));
+    }
+    solutions
+}
+
+// Angles of the rotation about the axis through r along w that put p at distance delta from q
+fn subproblem3(w: &Vector3<f64>, r: &Vector3<f64>, p: &Vector3<f64>, q: &Vector3<f64>, delta: f64) -> Vec<f64> {
+    let u = (p - r) - w * w.dot(&(p - r));
+    let v = (q - r) - w * w.dot(&(q - r));
+    let delta_squared = delta * delta - w.dot(&(p - q)).powi(2);
+    let theta0 = w.dot(&u.cross(&v)).atan2(u.dot(&v));
+    let cos = (u.norm_squared() + v.norm_squared() - delta_squared) / (2.0 * u.norm() * v.norm());
+    if !cos.is_finite() || cos.abs() > 1.0 + 1e-9 {
+        return vec![];
+    }
+    let phi = cos.clamp(-1.0, 1.0).acos();
+    vec![theta0 - phi, theta0 + phi]
+}
+
+// Angles of the rotation about w with a . rot(w, t) * b = d
+fn subproblem4(w: &Vector3<f64>, a: &Vector3<f64>, b: &Vector3<f64>, d: f64) -> Vec<f64> {
+    let b_parallel = w * w.dot(b);
+    let x = a.dot(&(b - b_parallel));
+    let y = a.dot(&w.cross(b));
+    let c = d - a.dot(&b_parallel);
+    let norm = (x * x + y * y).sqrt();
+    if norm < 1e-12 || (c / norm).abs() > 1.0 + 1e-9 {
+        return vec![];
+    }
+    let phi = y.atan2(x);
+    let delta = (c / norm).clamp(-1.0, 1.0).acos();
+    vec![phi - delta, phi + delta]
+}
+
+// Angles with rot(w1, t1) * rot(w2, t2) * rot(w3, t3) = rotation
+fn euler_angles(w: [&Vector3<f64>; 3], rotation: &UnitQuaternion<f64>) -> Vec<[f64; 3]> {
+    let mut solutions = Vec::new();
+    for (t1, t2) in subproblem2(w[0], w[1], w[2], &(rotation * w[2])) {
+        let rotation12 = UnitQuaternion::from_axis_angle(&Unit::new_unchecked(*w[0]), t1)
+            * UnitQuaternion::from_axis_angle(&Unit::new_unchecked(*w[1]), t2);
+        let v = perpendicular(w[2]);
+        let t3 = subproblem1(w[2], &v, &(rotation12.inverse() * rotation * v));
+        solutions.push([t1, t2, t3]);
+    }
+    solutions
+}
@@ -1,13 +1,23 @@
-use nalgebra::{Vector3, Vector6, UnitQuaternion, DMatrix, Matrix3};
+use nalgebra::{Vector3, Vector6, UnitQuaternion, DMatrix, Matrix3, Unit, Isometry3};
 use urdf_rs;
+use crate::utils_rust::autodiff::{Real, Quat, vec3_from_f64};
 
 #[derive(Clone, Debug)]
 pub struct ArmKineamtics {
     pub axis_types: Vec<String>,
+    // unit axis of each movable joint, expressed in the joint frame
+    pub joint_axes: Vec<Vector3<f64>>,
     pub displacements: Vec<Vector3<f64>>,
     pub rot_offset_quats: Vec<UnitQuaternion<f64>>,
     pub joint_types: Vec<String>,
+    // name of the link attached to each frame returned by get_frames_immutable
+    pub link_names: Vec<String>,
     pub num_dof: usize,
+    // pose of the base link in the world frame, where all frames are expressed
+    pub base_pose: Isometry3<f64>,
+    // tool center point in the end effector frame. If set, it is the last frame and it stands in
+    // for the end effector in the pose objectives and the jacobian.
+    pub tcp_offset: Option<Isometry3<f64>>,
     pub out_positions: Vec<Vector3<f64>>,
     pub out_rot_quats: Vec<UnitQuaternion<f64>>,
     __do_rot_offset: Vec<bool>,
@@ -19,7 +29,8 @@ pub struct ArmKineamtics {
     __is_z: Vec<bool>,
     __is_neg_x: Vec<bool>,
     __is_neg_y: Vec<bool>,
-    __is_neg_z: Vec<bool>
+    __is_neg_z: Vec<bool>,
+    __is_general_axis: Vec<bool>
 }
 
 impl ArmKineamtics{
@@ -30,27 +41,36 @@ impl ArmKineamtics{
         let mut joint_types: Vec<String> = Vec::new();
         let mut disp_offsets:Vec<Vector3<f64>> = Vec::new();
         let mut rot_offsets: Vec<UnitQuaternion<f64>> = Vec::new();
+        let mut link_names: Vec<String> = Vec::new();
+        let mut joint_axes: Vec<Vector3<f64>> = Vec::new();
 
         if movable_base {
             // x-translation
             axis_types.push("x".to_string());
+            joint_axes.push(Vector3::x());
             joint_types.push("prismatic".to_string());
             disp_offsets.push(Vector3::new(0.,0.,0.));
             rot_offsets.push(UnitQuaternion::identity());
             // y-translation
             axis_types.push("y".to_string());
+            joint_axes.push(Vector3::y());
             joint_types.push("prismatic".to_string());
             disp_offsets.push(Vector3::new(0.,0.,0.));
             rot_offsets.push(UnitQuaternion::identity());
             // z-rotation
             axis_types.push("z".to_string());
+            joint_axes.push(Vector3::z());
             joint_types.push("revolute".to_string());
             disp_offsets.push(Vector3::new(0.,0.,0.));
             rot_offsets.push(UnitQuaternion::identity());
+            link_names.push(String::new());
+            link_names.push(String::new());
+            link_names.push(String::new());
         }
 
         let mut first_link: bool = true;
         chain.iter().for_each(|node| {
+            link_names.push(node.link().as_ref().map(|link| link.name.clone()).unwrap_or_default());
             let joint = node.joint();
             if first_link {
                 first_link = false;
@@ -61,19 +81,8 @@ impl ArmKineamtics{
                         joint_types.push("fixed".to_string());
                     },
                     k::JointType::Rotational { axis } => {
-                        if axis[0] == 1.0 {
-                            axis_types.push("x".to_string());
-                        } else if axis[1] == 1.0 {
-                            axis_types.push("y".to_string());
-                        } else if axis[2] == 1.0 {
-                            axis_types.push("z".to_string());
-                        } else if axis[0] == -1.0 {
-                            axis_types.push("-x".to_string());
-                        } else if axis[1] == -1.0 {
-                            axis_types.push("-y".to_string());
-                        } else if axis[2] == -1.0 {
-                            axis_types.push("-z".to_string());
-                        }           
+                        axis_types.push(axis_type(&axis));
+                        joint_axes.push(axis.into_inner());
                         if joint.limits.is_none() {
                             joint_types.push("continuous".to_string());
                         } else {
@@ -81,19 +90,8 @@ impl ArmKineamtics{
                         }     
                     },
                     k::JointType::Linear { axis } => {
-                        if axis[0] == 1.0 {
-                            axis_types.push("x".to_string());
-                        } else if axis[1] == 1.0 {
-                            axis_types.push("y".to_string());
-                        } else if axis[2] == 1.0 {
-                            axis_types.push("z".to_string());
-                        } else if axis[0] == -1.0 {
-                            axis_types.push("-x".to_string());
-                        } else if axis[1] == -1.0 {
-                            axis_types.push("-y".to_string());
-                        } else if axis[2] == -1.0 {
-                            axis_types.push("-z".to_string());
-                        }
+                        axis_types.push(axis_type(&axis));
+                        joint_axes.push(axis.into_inner());
                         joint_types.push("prismatic".to_string());
                     }
                 }
@@ -102,14 +100,17 @@ impl ArmKineamtics{
             rot_offsets.push(joint.origin().rotation);
         });
         // call init to initialize the Arm struct
-        ArmKineamtics::init(axis_types, disp_offsets, rot_offsets, joint_types)
+        let mut arm = ArmKineamtics::init(axis_types, joint_axes, disp_offsets, rot_offsets, joint_types);
+        arm.link_names = link_names;
+        arm
     }
 
-    pub fn init(axis_types: Vec<String>,
+    pub fn init(axis_types: Vec<String>, joint_axes: Vec<Vector3<f64>>,
         disp_offsets: Vec<Vector3<f64>>,
         rot_offsets: Vec<UnitQuaternion<f64>>, joint_types: Vec<String>) -> ArmKineamtics {
 
         let num_dof = axis_types.len();
+        assert!(joint_axes.len() == num_dof, "Number of joint axes: {}, number of dofs: {}", joint_axes.len(), num_dof);
 
         let mut __do_rot_offset: Vec<bool> = Vec::new();
         for i in 0..rot_offsets.len() {
@@ -164,6 +165,7 @@ impl ArmKineamtics{
         let mut __is_neg_x: Vec<bool> = Vec::new();
         let mut __is_neg_y: Vec<bool> = Vec::new();
         let mut __is_neg_z: Vec<bool> = Vec::new();
+        let mut __is_general_axis: Vec<bool> = Vec::new();
         for i in 0..axis_types.len() {
             __is_x.push(false);
             __is_y.push(false);
@@ -171,6 +173,7 @@ impl ArmKineamtics{
             __is_neg_x.push(false);
             __is_neg_y.push(false);
             __is_neg_z.push(false);
+            __is_general_axis.push(false);
             if axis_types[i] == String::from("X") || axis_types[i] == String::from("x") {
                 __is_x[i] = true;
             }
@@ -192,6 +195,9 @@ impl ArmKineamtics{
             else if axis_types[i] == String::from("-z"){
                 __is_neg_z[i] = true;
             }
+            else {
+                __is_general_axis[i] = true;
+            }
         }
 
         println!("displacements: {:?}", displacements);
@@ -200,126 +206,163 @@ impl ArmKineamtics{
         println!("__do_rot_offset: {:?}", __do_rot_offset);
         println!("rot_offset_quats: {:?}", rot_offset_quats);
         println!("joint_types: {:?}", joint_types);
-        ArmKineamtics{axis_types, displacements, rot_offset_quats,
-            joint_types, num_dof, out_positions, out_rot_quats, __do_rot_offset, __is_prismatic,
+        ArmKineamtics{axis_types, joint_axes, displacements, rot_offset_quats,
+            joint_types, link_names: Vec::new(), num_dof, base_pose: Isometry3::identity(), tcp_offset: None, out_positions, out_rot_quats, __do_rot_offset, __is_prismatic,
             __is_revolute_or_continuous, __is_fixed, __is_x, __is_y, __is_z, __is_neg_x, __is_neg_y,
-            __is_neg_z}
+            __is_neg_z, __is_general_axis}
+    }
+
+    fn joint_rotation<T: Real>(&self, joint_idx: usize, joint_val: T) -> Quat<T> {
+        if self.__is_x[joint_idx] || self.__is_neg_x[joint_idx] || self.__is_y[joint_idx]
+            || self.__is_neg_y[joint_idx] || self.__is_z[joint_idx] || self.__is_neg_z[joint_idx] {
+            let half = joint_val * 0.5;
+            let (c, s) = (half.cos(), half.sin());
+            let zero = T::zero();
+            if self.__is_x[joint_idx] {
+                Quat::new(c, s, zero, zero)
+            } else if self.__is_y[joint_idx] {
+                Quat::new(c, zero, s, zero)
+            } else if self.__is_z[joint_idx] {
+                Quat::new(c, zero, zero, s)
+            } else if self.__is_neg_x[joint_idx] {
+                Quat::new(c, -s, zero, zero)
+            } else if self.__is_neg_y[joint_idx] {
+                Quat::new(c, zero, -s, zero)
+            } else {
+                Quat::new(c, zero, zero, -s)
+            }
+        } else {
+            Quat::from_axis_angle(&self.joint_axes[joint_idx], joint_val)
+        }
     }
 
     pub fn get_frames_immutable(&self, x: &[f64]) -> (Vec<Vector3<f64>>, Vec<UnitQuaternion<f64>>) {
-        let mut out_positions: Vec<Vector3<f64>> = Vec::new();
-        let mut out_rot_quats: Vec<UnitQuaternion<f64>> = Vec::new();
+        let mut out_positions: Vec<Vector3<f64>> = Vec::with_capacity(self.displacements.len() + 1);
+        let mut out_rot_quats: Vec<UnitQuaternion<f64>> = Vec::with_capacity(self.displacements.len() + 2);
+        self.get_frames_into(x, &mut out_positions, &mut out_rot_quats);
+        (out_positions, out_rot_quats)
+    }
+
+    // Same as get_frames_immutable, into out_positions and out_rot_quats of the arm, so that
+    // repeated calls do not allocate
+    pub fn get_frames(&mut self, x: &[f64]) {
+        let mut out_positions = std::mem::take(&mut self.out_positions);
+        let mut out_rot_quats = std::mem::take(&mut self.out_rot_quats);
+        self.get_frames_into(x, &mut out_positions, &mut out_rot_quats);
+        self.out_positions = out_positions;
+        self.out_rot_quats = out_rot_quats;
+    }
+
+    // Same as get_frames_immutable, into the given buffers, which are cleared first
+    pub fn get_frames_into(&self, x: &[f64], out_positions: &mut Vec<Vector3<f64>>, out_rot_quats: &mut Vec<UnitQuaternion<f64>>) {
+        out_positions.clear();
+        out_rot_quats.clear();
+
+        let mut pt: Vector3<f64> = self.base_pose.translation.vector;
+        let mut rot_quat: UnitQuaternion<f64> = self.base_pose.rotation;
 
-        let mut pt: Vector3<f64> = Vector3::new(0.,0.,0.);
-        let mut rot_quat = UnitQuaternion::identity();
-       
         out_positions.push(pt);
         out_rot_quats.push(rot_quat);
 
         let mut joint_idx: usize = 0;
         for i in 0..self.displacements.len() {
+            pt += rot_quat * self.displacements[i];
+            if self.__do_rot_offset[i] {
+                rot_quat *= self.rot_offset_quats[i];
+            }
+
             if self.__is_revolute_or_continuous[i] {
+                rot_quat *= self.joint_rotation(joint_idx, x[joint_idx]).value();
+                joint_idx += 1;
+            }
+            else if self.__is_prismatic[i] {
+                pt += rot_quat * (self.joint_axes[joint_idx] * x[joint_idx]);
+                joint_idx += 1;
+            }
 
-                pt = rot_quat * self.displacements[i] + pt;
+            out_positions.push(pt);
+            out_rot_quats.push(rot_quat);
+        }
+        if let Some(tcp_offset) = &self.tcp_offset {
+            pt += rot_quat * tcp_offset.translation.vector;
+            rot_quat *= tcp_offset.rotation;
+            out_positions.push(pt);
+            out_rot_quats.push(rot_quat);
+        }
+        out_rot_quats.push(rot_quat);
+    }
 
-                if self.__do_rot_offset[i] {
-                    rot_quat = rot_quat * self.rot_offset_quats[i];
-                }
+    // Forward kinematics over any Real scalar, e.g. dual numbers to differentiate through the chain
+    pub fn get_frames_generic<T: Real>(&self, x: &[T]) -> (Vec<Vector3<T>>, Vec<Quat<T>>) {
+        let mut out_positions: Vec<Vector3<T>> = Vec::with_capacity(self.displacements.len() + 2);
+        let mut out_rot_quats: Vec<Quat<T>> = Vec::with_capacity(self.displacements.len() + 3);
 
-                let joint_val = x[joint_idx];
-                if self.__is_x[joint_idx] {
-                    rot_quat = rot_quat * get_quat_x(joint_val);
-                } else if self.__is_y[joint_idx] {
-                    rot_quat = rot_quat * get_quat_y(joint_val);
-                } else if self.__is_z[joint_idx] {
-                    rot_quat = rot_quat * get_quat_z(joint_val);
-                } else if self.__is_neg_x[joint_idx] {
-                    rot_quat = rot_quat * get_quat_x(-joint_val);
-                } else if self.__is_neg_y[joint_idx] {
-                    rot_quat = rot_quat * get_quat_y(-joint_val);
-                } else if self.__is_neg_z[joint_idx] {
-                    rot_quat = rot_quat * get_quat_z(-joint_val);
-                }
+        let mut pt: Vector3<T> = vec3_from_f64(&self.base_pose.translation.vector);
+        let mut rot_quat: Quat<T> = Quat::from_unit_quaternion(&self.base_pose.rotation);
+       
+        out_positions.push(pt);
+        out_rot_quats.push(rot_quat);
 
-                out_positions.push(pt.clone());
-                out_rot_quats.push(rot_quat.clone());
+        let mut joint_idx: usize = 0;
+        for i in 0..self.displacements.len() {
+            pt = rot_quat.rotate(&vec3_from_f64(&self.displacements[i])) + pt;
+            if self.__do_rot_offset[i] {
+                rot_quat = rot_quat.mul(&Quat::from_unit_quaternion(&self.rot_offset_quats[i]));
+            }
 
+            if self.__is_revolute_or_continuous[i] {
+                rot_quat = rot_quat.mul(&self.joint_rotation(joint_idx, x[joint_idx]));
                 joint_idx += 1;
             }
             else if self.__is_prismatic[i] {
-                let joint_val = x[joint_idx];
-                if self.__is_x[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(joint_val, 0., 0.);
-                } else if self.__is_y[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., joint_val, 0.);
-                } else if self.__is_z[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., 0., joint_val);
-                } else if self.__is_neg_x[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(-joint_val, 0., 0.);
-                } else if self.__is_neg_y[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., -joint_val, 0.);
-                } else if self.__is_neg_z[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., 0., -joint_val);
-                }
-                if self.__do_rot_offset[i] {
-                    rot_quat = rot_quat * self.rot_offset_quats[i];
-                }
-                out_positions.push(pt.clone());
-                out_rot_quats.push(rot_quat.clone());
+                // the joint translates along its axis in the joint frame
+                pt = rot_quat.rotate(&(vec3_from_f64(&self.joint_axes[joint_idx]) * x[joint_idx])) + pt;
                 joint_idx += 1;
             }
-            else {
-                pt = rot_quat * self.displacements[i] + pt;
-                if self.__do_rot_offset[i] {
-                    rot_quat = rot_quat * self.rot_offset_quats[i];
-                }
-                out_positions.push(pt.clone());
-                out_rot_quats.push(rot_quat.clone());
-            }
+
+            out_positions.push(pt);
+            out_rot_quats.push(rot_quat);
         }
-        out_rot_quats.push(rot_quat.clone());
+        if let Some(tcp_offset) = &self.tcp_offset {
+            pt = rot_quat.rotate(&vec3_from_f64(&tcp_offset.translation.vector)) + pt;
+            rot_quat = rot_quat.mul(&Quat::from_unit_quaternion(&tcp_offset.rotation));
+            out_positions.push(pt);
+            out_rot_quats.push(rot_quat);
+        }
+        out_rot_quats.push(rot_quat);
 
         (out_positions, out_rot_quats)
     }
 
+    // Geometric jacobian of the end effector, expressed in the world frame. Rows are linear velocity
+    // followed by angular velocity, columns are the dofs of the chain.
     pub fn get_jacobian_immutable(&self, x: &[f64]) -> DMatrix<f64> {
         let (joint_positions, joint_rot_quats) = self.get_frames_immutable(x);
+        self.get_jacobian_from_frames(&joint_positions, &joint_rot_quats)
+    }
 
+    // Same as get_jacobian_immutable, from frames that were already computed
+    pub fn get_jacobian_from_frames(&self, joint_positions: &[Vector3<f64>], joint_rot_quats: &[UnitQuaternion<f64>]) -> DMatrix<f64> {
         let ee_position = joint_positions[joint_positions.len()-1];
-        let pos_x: Vector3<f64> = Vector3::new(1.0, 0.0, 0.0);
-        let pos_y: Vector3<f64> = Vector3::new(0.0, 1.0, 0.0);
-        let pos_z: Vector3<f64> = Vector3::new(0.0, 0.0, 1.0);
-        let neg_x: Vector3<f64> = Vector3::new(-1.0, 0.0, 0.0);
-        let neg_y: Vector3<f64> = Vector3::new(0.0, -1.0, 0.0);
-        let neg_z: Vector3<f64> = Vector3::new(0.0, 0.0, -1.0);
-
-        let mut disp: Vector3<f64> = Vector3::new(0.0, 0.0, 0.0);
-        let mut p_axis: Vector3<f64> = Vector3::new(0.0, 0.0, 0.0);
         let mut joint_idx: usize = 0;
 
-        let mut jacobian: DMatrix<f64> = DMatrix::identity(6, x.len());
-
-        for i in 1..self.displacements.len() {
-            if self.__is_revolute_or_continuous[i-1] {
-                disp = ee_position - joint_positions[i];
-                if self.__is_x[joint_idx] {
-                    p_axis = joint_rot_quats[i] * pos_x
-                } else if self.__is_y[joint_idx] {
-                    p_axis = joint_rot_quats[i] * pos_y
-                } else if self.__is_z[joint_idx] {
-                    p_axis = joint_rot_quats[i] * pos_z
-                } else if self.__is_neg_x[joint_idx] {
-                    p_axis = joint_rot_quats[i] * neg_x
-                } else if self.__is_neg_y[joint_idx] {
-                    p_axis = joint_rot_quats[i] * neg_y
-                } else if self.__is_neg_z[joint_idx] {
-                    p_axis = joint_rot_quats[i] * neg_z
-                } 
+        let mut jacobian: DMatrix<f64> = DMatrix::zeros(6, self.num_dof);
+
+        // frame i+1 is the frame right after joint i
+        for i in 0..self.displacements.len() {
+            if self.__is_revolute_or_continuous[i] {
+                let disp = ee_position - joint_positions[i+1];
+                let p_axis = joint_rot_quats[i+1] * self.joint_axes[joint_idx];
 
                 let linear = p_axis.cross(&disp);
                 jacobian.set_column(joint_idx, & Vector6::new( linear.x, linear.y, linear.z,
                                                                     p_axis.x, p_axis.y, p_axis.z ));
 
+                joint_idx += 1;
+            } else if self.__is_prismatic[i] {
+                let p_axis = joint_rot_quats[i+1] * self.joint_axes[joint_idx];
+                jacobian.set_column(joint_idx, & Vector6::new( p_axis.x, p_axis.y, p_axis.z, 0., 0., 0. ));
+
                 joint_idx += 1;
             }
         }
@@ -327,75 +370,80 @@ impl ArmKineamtics{
         jacobian
     }
 
+    // Axis of each movable joint and a point on it, in the world frame
+    pub fn get_joint_axes_immutable(&self, x: &[f64]) -> (Vec<Vector3<f64>>, Vec<Vector3<f64>>) {
+        let (joint_positions, joint_rot_quats) = self.get_frames_immutable(x);
+        let mut axes = Vec::with_capacity(self.num_dof);
+        let mut points = Vec::with_capacity(self.num_dof);
+        let mut joint_idx: usize = 0;
+        for i in 0..self.displacements.len() {
+            if self.__is_revolute_or_continuous[i] || self.__is_prismatic[i] {
+                axes.push(joint_rot_quats[i+1] * self.joint_axes[joint_idx]);
+                points.push(joint_positions[i+1]);
+                joint_idx += 1;
+            }
+        }
+        (axes, points)
+    }
+
     pub fn get_manipulability_immutable(&self, x: &[f64]) -> f64 {
         let jacobian = self.get_jacobian_immutable(x);
         (jacobian.clone() * jacobian.transpose()).determinant().sqrt()
     }
 
     pub fn get_ee_pos_and_quat_immutable(&self, x: &[f64]) -> (Vector3<f64>, UnitQuaternion<f64>) {
-        let mut pt: Vector3<f64> = Vector3::new(0.,0.,0.);
-        let mut rot_quat = UnitQuaternion::identity();
+        let (pt, rot_quat) = self.get_ee_pos_and_quat_generic(x);
+        (pt, rot_quat.value())
+    }
+
+    pub fn get_ee_pos_and_quat_generic<T: Real>(&self, x: &[T]) -> (Vector3<T>, Quat<T>) {
+        let mut pt: Vector3<T> = vec3_from_f64(&self.base_pose.translation.vector);
+        let mut rot_quat: Quat<T> = Quat::from_unit_quaternion(&self.base_pose.rotation);
 
         assert!(x.len() == self.num_dof, "x length is not equal to num_dof! x.len(): {}, num_dof: {}", x.len(), self.num_dof);
 
         let mut joint_idx: usize = 0;
         for i in 0..self.displacements.len() {
-            if self.__is_revolute_or_continuous[i] {
-                pt = rot_quat * self.displacements[i] + pt;
-
-                if self.__do_rot_offset[i] {
-                    rot_quat = rot_quat * self.rot_offset_quats[i];
-                }
-
-                let joint_val = x[joint_idx];
-                if self.__is_x[joint_idx] {
-                    rot_quat = rot_quat * get_quat_x(joint_val);
-                } else if self.__is_y[joint_idx] {
-                    rot_quat = rot_quat * get_quat_y(joint_val);
-                } else if self.__is_z[joint_idx] {
-                    rot_quat = rot_quat * get_quat_z(joint_val);
-                } else if self.__is_neg_x[joint_idx] {
-                    rot_quat = rot_quat * get_quat_x(-joint_val);
-                } else if self.__is_neg_y[joint_idx] {
-                    rot_quat = rot_quat * get_quat_y(-joint_val);
-                } else if self.__is_neg_z[joint_idx] {
-                    rot_quat = rot_quat * get_quat_z(-joint_val);
-                }
+            pt = rot_quat.rotate(&vec3_from_f64(&self.displacements[i])) + pt;
+            if self.__do_rot_offset[i] {
+                rot_quat = rot_quat.mul(&Quat::from_unit_quaternion(&self.rot_offset_quats[i]));
+            }
 
+            if self.__is_revolute_or_continuous[i] {
+                rot_quat = rot_quat.mul(&self.joint_rotation(joint_idx, x[joint_idx]));
                 joint_idx += 1;
             }
             else if self.__is_prismatic[i] {
-                let joint_val = x[joint_idx];
-                if self.__is_x[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(joint_val, 0., 0.);
-                } else if self.__is_y[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., joint_val, 0.);
-                } else if self.__is_z[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., 0., joint_val);
-                } else if self.__is_neg_x[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(-joint_val, 0., 0.);
-                } else if self.__is_neg_y[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., -joint_val, 0.);
-                } else if self.__is_neg_z[joint_idx] {
-                    pt = rot_quat * self.displacements[i] + pt + Vector3::new(0., 0., -joint_val);
-                }
-                if self.__do_rot_offset[i] {
-                    rot_quat = rot_quat * self.rot_offset_quats[i];
-                }
+                // the joint translates along its axis in the joint frame
+                pt = rot_quat.rotate(&(vec3_from_f64(&self.joint_axes[joint_idx]) * x[joint_idx])) + pt;
                 joint_idx += 1;
             }
-            else {
-                pt = rot_quat * self.displacements[i] + pt;
-                if self.__do_rot_offset[i] {
-                    rot_quat = rot_quat * self.rot_offset_quats[i];
-                }
-            }
+        }
+        if let Some(tcp_offset) = &self.tcp_offset {
+            pt = rot_quat.rotate(&vec3_from_f64(&tcp_offset.translation.vector)) + pt;
+            rot_quat = rot_quat.mul(&Quat::from_unit_quaternion(&tcp_offset.rotation));
         }
 
         (pt, rot_quat)
     }
 }
 
+// Axis-aligned joints (up to numerical noise in the URDF) use the fast path in forward kinematics
+pub fn axis_type(axis: &Unit<Vector3<f64>>) -> String {
+    let tol = 1e-6;
+    let names = ["x", "y", "z"];
+    for i in 0..3 {
+        let mut e = Vector3::zeros();
+        e[i] = 1.0;
+        if (axis.into_inner() - e).norm() < tol {
+            return names[i].to_string();
+        } else if (axis.into_inner() + e).norm() < tol {
+            return format!("-{}", names[i]);
+        }
+    }
+    "general".to_string()
+}
+
 pub fn get_rot_x(val: f64) -> Matrix3<f64> {
     Matrix3::new(1., 0., 0., 0., val.cos(), -val.sin(), 0.0, val.sin(), val.cos())
 }
@@ -0,0 +1,77 @@
+use nalgebra::Vector3;
+use crate::spacetime::robot_kinematics::RobotKinematics;
+use std::fmt;
+
+// A vector of the arm at a configuration: the axis of a joint, or the vector from a point on the
+// axis of the first joint to a point on the axis of the second joint. Joints are indexed along
+// the first chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArmVector {
+    Axis(usize),
+    Link(usize, usize),
+}
+
+// A yes/no test that tells IK branches apart, e.g. elbow up or down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BranchTest {
+    // sin(x[dof] - offset) >= 0
+    JointSign{dof: usize, offset: f64},
+    // (a x b) . c >= 0, e.g. the side of the shoulder-wrist line the elbow is on
+    TripleProduct(ArmVector, ArmVector, ArmVector),
+}
+
+impl BranchTest {
+    pub fn check(&self, x: &[f64], axes: &[Vector3<f64>], points: &[Vector3<f64>]) -> bool {
+        match self {
+            BranchTest::JointSign{dof, offset} => (x[*dof] - offset).sin() >= 0.0,
+            BranchTest::TripleProduct(a, b, c) => {
+                let vector = |v: &ArmVector| match v {
+                    ArmVector::Axis(i) => axes[*i],
+                    ArmVector::Link(i, j) => points[*j] - points[*i],
+                };
+                vector(a).cross(&vector(b)).dot(&vector(c)) >= 0.0
+            }
+        }
+    }
+}
+
+// The flags of the branch a configuration is on, in the order of the classifier tests
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BranchLabel {
+    pub flags: Vec<(String, bool)>,
+}
+
+impl BranchLabel {
+    pub fn get(&self, name: &str) -> Option<bool> {
+        self.flags.iter().find(|(flag_name, _)| flag_name == name).map(|(_, flag)| *flag)
+    }
+}
+
+// e.g. "shoulder+ elbow- wrist+"
+impl fmt::Display for BranchLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags: Vec<String> = self.flags.iter().map(|(name, flag)| format!("{}{}", name, if *flag { "+" } else { "-" })).collect();
+        write!(f, "{}", flags.join(" "))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BranchClassifier {
+    pub tests: Vec<(String, BranchTest)>,
+}
+
+impl BranchClassifier {
+    pub fn new(tests: Vec<(String, BranchTest)>) -> Self {
+        Self{tests}
+    }
+
+    pub fn label(&self, robot: &RobotKinematics, x: &[f64]) -> BranchLabel {
+        if self.tests.is_empty() {
+            return BranchLabel::default();
+        }
+        let chain_x: Vec<f64> = robot.get_chain_values(x, 0);
+        let (axes, points) = robot.arms[0].get_joint_axes_immutable(&chain_x);
+        let flags = self.tests.iter().map(|(name, test)| (name.clone(), test.check(x, &axes, &points))).collect();
+        BranchLabel{flags}
+    }
+}
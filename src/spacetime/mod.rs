@@ -1,4 +1,6 @@
 pub mod robot_kinematics;
 pub mod arm_kinematics;
 pub mod robot;
+pub mod analytic_ik;
+pub mod branches;
 pub mod motion;
\ No newline at end of file
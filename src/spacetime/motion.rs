@@ -1,10 +1,13 @@
 
 use ndarray::Array1;
+use crate::spacetime::branches::BranchLabel;
 
 pub struct Motion {
     pub robot_name: String,
     pub joint_names: Vec<String>,
     pub data: Vec<(f64, Array1<f64>)>,
+    // branch of each configuration in data, empty labels if the robot has no branch tests
+    pub branch_labels: Vec<BranchLabel>,
 }
 
 impl Motion {
@@ -13,6 +16,7 @@ impl Motion {
             robot_name: robot_name.to_string(),
             joint_names: vec![],
             data: vec![],
+            branch_labels: vec![],
         }
     }
 }
\ No newline at end of file
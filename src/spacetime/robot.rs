@@ -1,7 +1,10 @@
-use::nalgebra::{Vector3, UnitQuaternion};
+use::nalgebra::{Vector3, UnitQuaternion, DMatrix, DVector};
 use rand::Rng;
 use crate::utils_rust::file_utils::{*};
-use crate::relaxed_ik::RelaxedIK;
+use crate::relaxed_ik::{RelaxedIK, ObjectiveMasterFactory};
+use crate::groove::groove::SolveResult;
+use crate::spacetime::analytic_ik::AnalyticIK;
+use crate::spacetime::branches::{BranchClassifier, BranchLabel};
 use ndarray::Array1;
 
 pub struct Robot {
@@ -9,31 +12,73 @@ pub struct Robot {
     pub ik_solver: RelaxedIK,
     pub rng: rand::prelude::ThreadRng,
     pub arm_num_dofs: usize,
+    // closed-form IK, if the arm has one of the layouts in analytic_ik
+    pub analytic_ik: Option<AnalyticIK>,
+    // the branch_flags of the settings, or else the geometric tests of the analytic IK layout
+    pub branch_classifier: BranchClassifier,
 }
 
 impl Robot {
 
     pub fn new(robot_name: &str) -> Self {
+        Self::with_objectives(robot_name, &RelaxedIK::default_objective_masters)
+    }
+
+    // Uses the given factory for the objectives of try_to_track (first) and try_to_reach (second)
+    pub fn with_objectives(robot_name: &str, objective_master_factory: &ObjectiveMasterFactory) -> Self {
         let path_to_src = get_path_to_src();
         let arm_path_to_setting = path_to_src.clone() +  "configs/example_settings/"+ robot_name + ".yaml";
-        let ik_solver = RelaxedIK::load_settings(&arm_path_to_setting);
+        let mut ik_solver = RelaxedIK::load_settings_with_objectives(&arm_path_to_setting, objective_master_factory);
+        // candidates are filtered by self-collisions
+        ik_solver.vars.compute_allowed_collision_matrix();
        
         let arm_num_dofs = ik_solver.vars.robot.num_dofs;
         assert!(ik_solver.vars.robot.chain_indices.len() == 1, "Robot should have only one chain");
+        let analytic_ik = AnalyticIK::from_robot(&ik_solver.vars.robot);
+        if let Some(analytic_ik) = &analytic_ik {
+            println!("Using analytic IK for {} ({:?})", robot_name, analytic_ik.layout);
+        }
+        let branch_classifier = if !ik_solver.vars.branch_tests.is_empty() {
+            BranchClassifier::new(ik_solver.vars.branch_tests.clone())
+        } else {
+            BranchClassifier::new(analytic_ik.as_ref().map(|analytic_ik| analytic_ik.branch_tests()).unwrap_or_default())
+        };
         Robot {
             robot_name: robot_name.to_string(),
             ik_solver,
             rng: rand::thread_rng(),
             arm_num_dofs,
+            analytic_ik,
+            branch_classifier,
         }
     }
+
+    // Returns true if the arm intersects itself or an environment obstacle
+    pub fn check_collision(&self, config: &Array1<f64>) -> bool {
+        let frames = self.ik_solver.vars.robot.get_frames_immutable(&config.to_vec());
+        self.ik_solver.vars.self_collision.check_collision(&frames) || self.ik_solver.vars.env_collision.check_collision(&frames[0])
+    }
+
+    // Checks the straight-line joint interpolation between two configurations at the given joint resolution
+    pub fn check_motion_collision(&self, config1: &Array1<f64>, config2: &Array1<f64>, resolution: f64) -> bool {
+        let max_diff = (config2 - config1).iter().fold(0.0_f64, |acc, d| acc.max(d.abs()));
+        let num_steps = (max_diff / resolution).ceil().max(1.0) as usize;
+        for step in 0..=num_steps {
+            let t = step as f64 / num_steps as f64;
+            let config = config1 + &((config2 - config1) * t);
+            if self.check_collision(&config) {
+                return true;
+            }
+        }
+        false
+    }
     pub fn get_random_arm_config(&mut self) -> Vec<f64> {
         let mut config = vec![];
         for i in 0..self.arm_num_dofs {
-            if self.ik_solver.vars.robot.joint_types[i] == "continuous" {
+            if self.ik_solver.vars.robot.joints[i].joint_type == "continuous" {
                 config.push(self.rng.gen_range(-std::f64::consts::PI..std::f64::consts::PI));
             } else {
-                config.push(self.rng.gen_range(self.ik_solver.vars.robot.lower_joint_limits[i]..=self.ik_solver.vars.robot.upper_joint_limits[i]));
+                config.push(self.rng.gen_range(self.ik_solver.vars.robot.joints[i].lower_limit..=self.ik_solver.vars.robot.joints[i].upper_limit));
             }
         }
         config
@@ -43,6 +88,11 @@ impl Robot {
         self.ik_solver.vars.robot.get_ee_pos_and_quat_immutable(&config.to_vec())[0]
     }
 
+    // Empty if the robot has no branch tests
+    pub fn branch_label(&self, config: &Array1<f64>) -> BranchLabel {
+        self.branch_classifier.label(&self.ik_solver.vars.robot, &config.to_vec())
+    }
+
     pub fn check_pose(&self, config: &Array1<f64>, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> bool {
         let (ee_pos, ee_quat) = self.fk(config);
         let pos_diff = (ee_pos - pos).norm();
@@ -50,34 +100,158 @@ impl Robot {
         pos_diff < 0.001 && quat_diff < 0.01
     }
 
+    // Configurations of a redundant arm spread along the self-motion manifold through config, which
+    // reaches pos and quat. The manifold is walked both ways from config in half spacing steps:
+    // each step is projected into the null space of the jacobian and pulled back onto the pose with
+    // Newton steps, and a walk ends where it gets stuck, e.g. at a joint limit. Samples are at least
+    // spacing apart in joint space, from each other and from config.
+    pub fn sample_self_motion(&mut self, config: &Array1<f64>, pos: Vector3<f64>, quat: UnitQuaternion<f64>, num_samples: usize, spacing: f64) -> Vec<Array1<f64>> {
+        let n = self.arm_num_dofs;
+        let initial_direction = DVector::from_fn(n, |_, _| self.rng.gen_range(-1.0..1.0));
+        let mut samples: Vec<Array1<f64>> = vec![];
+        for (walk, sign) in [1.0, -1.0].iter().enumerate() {
+            let max_samples = if walk == 0 { num_samples.div_ceil(2) } else { num_samples };
+            let mut current = config.clone();
+            let mut direction = &initial_direction * *sign;
+            for _ in 0..num_samples * 4 {
+                if samples.len() >= max_samples {
+                    break;
+                }
+                let jacobian = self.ik_solver.vars.robot.get_jacobian_immutable(&current.to_vec());
+                let step = (DMatrix::identity(n, n) - damped_pseudo_inverse(&jacobian) * &jacobian) * direction;
+                if step.norm() < 1e-9 {
+                    break;
+                }
+                direction = step.scale(0.5 * spacing / step.norm());
+                current = match self.project_to_pose(&current + &Array1::from(direction.as_slice().to_vec()), pos, quat) {
+                    Some(projected) => projected,
+                    None => break
+                };
+                let far_enough = |other: &Array1<f64>| (other - &current).mapv(|d| d * d).sum().sqrt() >= spacing;
+                if far_enough(config) && samples.iter().all(far_enough) {
+                    samples.push(current.clone());
+                }
+            }
+        }
+        samples
+    }
+
+    // Newton steps from config towards the pose within the joint limits, None if they do not reach it
+    fn project_to_pose(&self, mut config: Array1<f64>, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> Option<Array1<f64>> {
+        let robot = &self.ik_solver.vars.robot;
+        for _ in 0..20 {
+            for i in 0..self.arm_num_dofs {
+                config[i] = config[i].clamp(robot.joints[i].lower_limit, robot.joints[i].upper_limit);
+            }
+            if self.check_pose(&config, pos, quat) {
+                return Some(config);
+            }
+            let (ee_pos, ee_quat) = self.fk(&config);
+            let mut error = DVector::zeros(6);
+            error.fixed_rows_mut::<3>(0).copy_from(&(pos - ee_pos));
+            error.fixed_rows_mut::<3>(3).copy_from(&(quat * ee_quat.inverse()).scaled_axis());
+            let step = damped_pseudo_inverse(&robot.get_jacobian_immutable(&config.to_vec())) * error;
+            config = config + Array1::from(step.as_slice().to_vec());
+        }
+        None
+    }
+
+    // Whether the sensor axis points at the target within the view cone and distance band
+    pub fn check_look_at(&self, config: &Array1<f64>, target: Vector3<f64>) -> bool {
+        let vars = &self.ik_solver.vars;
+        let (ee_pos, ee_quat) = self.fk(config);
+        let to_target = target - ee_pos;
+        let dist = to_target.norm();
+        let angle = (ee_quat * vars.look_at_axes[0]).angle(&to_target);
+        let (min_dist, max_dist) = vars.look_at_distance_bounds[0];
+        angle <= vars.look_at_cone_angles[0] && dist >= min_dist && dist <= max_dist
+    }
+
     pub fn reset_random(&mut self) {
         let config = self.get_random_arm_config();
         self.ik_solver.reset(config);
     }
 
-    pub fn try_to_reach(&mut self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> (bool, Array1<f64>) {
-        // pos and quat are wrt the base frame
+    pub fn try_to_reach(&mut self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> (bool, Array1<f64>, SolveResult) {
         self.reset_random();
+        self.continue_to_reach(pos, quat)
+    }
+
+    // Same as try_to_reach, starting from the last solution instead of a random config
+    pub fn continue_to_reach(&mut self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> (bool, Array1<f64>, SolveResult) {
+        // pos and quat are wrt the world frame, at the tool center point
         self.ik_solver.vars.goal_positions = vec![pos];
         self.ik_solver.vars.goal_quats = vec![quat];
-        let config = self.ik_solver.solve(false);    
+        let (config, result) = self.ik_solver.solve(false);    
         // println!("config: {:?}", config);
         if self.check_pose(&config, pos, quat) {
-            return (true, config);
+            return (true, config, result);
         } else {
-            return (false, Array1::from(vec![]));
+            return (false, Array1::from(vec![]), result);
         }
     }
 
-    pub fn try_to_track(&mut self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> (bool, Array1<f64>) {
-        // pos and quat are wrt the base frame
+    // Closed-form solutions of the pose. Solutions that miss the pose, e.g. because the axes of a
+    // calibrated URDF are slightly off the layout, are refined by continue_to_reach. Empty if the
+    // robot has no analytic IK.
+    pub fn try_to_reach_analytic(&mut self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> Vec<Array1<f64>> {
+        let seeds = match &self.analytic_ik {
+            Some(analytic_ik) => analytic_ik.solve(pos, quat),
+            None => return vec![]
+        };
+        let mut solutions = vec![];
+        for seed in seeds {
+            let config = Array1::from(seed);
+            if self.check_pose(&config, pos, quat) {
+                solutions.push(config);
+                continue;
+            }
+            self.ik_solver.reset(config.to_vec());
+            let (found_ik, config, _) = self.continue_to_reach(pos, quat);
+            if found_ik {
+                solutions.push(config);
+            }
+        }
+        solutions
+    }
+
+    pub fn try_to_track(&mut self, pos: Vector3<f64>, quat: UnitQuaternion<f64>) -> (bool, Array1<f64>, SolveResult) {
+        // pos and quat are wrt the world frame, at the tool center point
         self.ik_solver.vars.goal_positions = vec![pos];
         self.ik_solver.vars.goal_quats = vec![quat];
-        let config = self.ik_solver.solve(true);    
+        let (config, result) = self.ik_solver.solve(true);    
         if self.check_pose(&config, pos, quat) {
-            return (true, config);
+            return (true, config, result);
+        } else {
+            return (false, Array1::from(vec![]), result);
+        }
+    }
+
+    // Same as try_to_reach, continue_to_reach and try_to_track, for robots built with the look-at objectives
+    pub fn try_to_reach_look_at(&mut self, target: Vector3<f64>) -> (bool, Array1<f64>, SolveResult) {
+        self.reset_random();
+        self.continue_to_reach_look_at(target)
+    }
+
+    pub fn continue_to_reach_look_at(&mut self, target: Vector3<f64>) -> (bool, Array1<f64>, SolveResult) {
+        // target is wrt the world frame
+        self.ik_solver.vars.goal_look_at_points = vec![target];
+        let (config, result) = self.ik_solver.solve(false);
+        if self.check_look_at(&config, target) {
+            return (true, config, result);
+        } else {
+            return (false, Array1::from(vec![]), result);
+        }
+    }
+
+    pub fn try_to_track_look_at(&mut self, target: Vector3<f64>) -> (bool, Array1<f64>, SolveResult) {
+        // target is wrt the world frame
+        self.ik_solver.vars.goal_look_at_points = vec![target];
+        let (config, result) = self.ik_solver.solve(true);
+        if self.check_look_at(&config, target) {
+            return (true, config, result);
         } else {
-            return (false, Array1::from(vec![]));
+            return (false, Array1::from(vec![]), result);
         }
     }
 
@@ -85,9 +259,21 @@ impl Robot {
         assert!(config.len() == prev_config.len(), "config and prev_config should have the same length");
         assert!(config.len() == self.arm_num_dofs, "config and prev_config should have the same length as arm_num_dofs");
         
-        // TODO: parse joint velocity limits from URDF
         for i in 0..config.len() {
-            if (config[i] - prev_config[i]).abs() > self.ik_solver.vars.robot.joint_velocity_limits[i] * delta_t {
+            if (config[i] - prev_config[i]).abs() > self.ik_solver.vars.robot.joints[i].velocity_limit * delta_t {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Whether the change of joint velocity from prev_prev_config -> prev_config (over prev_delta_t)
+    // to prev_config -> config (over delta_t) is within the acceleration limits
+    pub fn check_acceleration(&self, config: &Array1<f64>, prev_config: &Array1<f64>, prev_prev_config: &Array1<f64>, delta_t: f64, prev_delta_t: f64) -> bool {
+        for i in 0..config.len() {
+            let velocity = (config[i] - prev_config[i]) / delta_t;
+            let prev_velocity = (prev_config[i] - prev_prev_config[i]) / prev_delta_t;
+            if (velocity - prev_velocity).abs() > self.ik_solver.vars.robot.joints[i].acceleration_limit * 0.5 * (delta_t + prev_delta_t) {
                 return false;
             }
         }
@@ -102,4 +288,11 @@ impl Robot {
         out.sqrt()
     }
 
-}
\ No newline at end of file
+}
+
+// J^T (J J^T + damping I)^-1, which stays bounded near singularities
+fn damped_pseudo_inverse(jacobian: &DMatrix<f64>) -> DMatrix<f64> {
+    let rows = jacobian.nrows();
+    let jjt = jacobian * jacobian.transpose() + DMatrix::identity(rows, rows) * 1e-6;
+    jacobian.transpose() * jjt.cholesky().unwrap().inverse()
+}
@@ -1,154 +1,294 @@
 use crate::spacetime::arm_kinematics;
 use nalgebra;
 use urdf_rs;
+use crate::utils_rust::autodiff::{Real, Quat};
+use crate::spacetime::motion::Motion;
+use std::collections::HashMap;
+
+// URDF link name, position and orientation of a frame
+pub type NamedFrame = (String, nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>);
 
 #[derive(Clone, Debug)]
 pub struct RobotKinematics {
     pub arms: Vec<arm_kinematics::ArmKineamtics>,
     pub num_chains: usize,
     pub num_dofs: usize,
+    // dof of each joint of each chain, and how the joint follows it as (multiplier, offset), which
+    // is (1.0, 0.0) unless the joint is a mimic joint
     pub chain_indices: Vec<Vec<usize>>,
-    pub lower_joint_limits: Vec<f64>,
-    pub upper_joint_limits: Vec<f64>,
-    pub joint_velocity_limits: Vec<f64>,
-    pub joint_names: Vec<String>,
-    pub joint_types: Vec<String>,
+    pub chain_mimic: Vec<Vec<(f64, f64)>>,
+    // one entry per dof
+    pub joints: Vec<JointInfo>,
+}
+
+#[derive(Clone, Debug)]
+pub struct JointInfo {
+    pub name: String,
+    // "revolute", "continuous" or "prismatic"
+    pub joint_type: String,
+    // -999.0 and 999.0 for continuous joints
+    pub lower_limit: f64,
+    pub upper_limit: f64,
+    // f64::INFINITY if there is no limit
+    pub velocity_limit: f64,
+    pub acceleration_limit: f64,
+    pub effort_limit: f64,
+    // chains the joint moves, directly or through mimic joints
+    pub chains: Vec<usize>,
 }
 
 impl RobotKinematics {
+    // Joints shared by several chains (e.g. a torso) are one dof, and mimic joints follow the dof of
+    // the joint they mimic instead of adding one. Dofs are in the order of joint_ordering, or else
+    // in the order they first appear along the chains.
     pub fn from_urdf(urdf: &str, base_links: &[String], ee_links: &[String], joint_ordering: Option<Vec<String>>) -> Self {
         let description : urdf_rs::Robot = urdf_rs::read_from_string(urdf).unwrap();
         let chain: k::Chain<f64> = k::Chain::from(description.clone());
+        let urdf_joints: HashMap<&str, &urdf_rs::Joint> = description.joints.iter().map(|j| (j.name.as_str(), j)).collect();
 
         let mut arms: Vec<arm_kinematics::ArmKineamtics> = Vec::new();
-        let num_chains = base_links.len();        
-        let mut chain_indices = Vec::new();        
-        let mut num_dofs = 0;
-        
-        let mut lower_joint_limits = Vec::new();
-        let mut upper_joint_limits: Vec<f64> = Vec::new();
-        let mut joint_velocity_limits: Vec<f64> = Vec::new();
-        let mut joint_names = Vec::new();
-        let mut joint_types = Vec::new();
+        let num_chains = base_links.len();
+        let mut chain_joints: Vec<Vec<(String, f64, f64)>> = Vec::new();
 
         for i in 0..num_chains {
-            let base_link = chain.find_link(base_links[i].as_str()).unwrap();
-            let ee_link = chain.find_link(ee_links[i].as_str()).unwrap();
-            let serial_chain = k::SerialChain::from_end_to_root(&ee_link, &base_link);
-
-            let mut joint_indices = Vec::new();
-            let mut articulated_joint_index = 0;
+            let base_link = chain.find_link(base_links[i].as_str()).unwrap_or_else(|| panic!("Unknown base link {}", base_links[i]));
+            let ee_link = chain.find_link(ee_links[i].as_str()).unwrap_or_else(|| panic!("Unknown ee link {}", ee_links[i]));
+            let serial_chain = k::SerialChain::from_end_to_root(ee_link, base_link);
 
-            let mut first_link: bool = true;
-            serial_chain.iter().for_each(|node| {
+            // the first node is the base link
+            let mut joints = Vec::new();
+            serial_chain.iter().skip(1).for_each(|node| {
                 let joint = node.joint();
-                if first_link {
-                    first_link = false;
-                    return
-                } else {
-                    let fixed_joint: bool;
-                    match joint.joint_type {
-                        k::JointType::Fixed => {
-                            fixed_joint = true;
-                        },
-                        k::JointType::Rotational { axis } => {
-                            if joint.limits.is_none() {
-                                lower_joint_limits.push(-999.0);
-                                upper_joint_limits.push(999.0);
-                                joint_types.push("continuous".to_string());
-                            } else {
-                                lower_joint_limits.push(joint.limits.unwrap().min);
-                                upper_joint_limits.push(joint.limits.unwrap().max);
-                                joint_types.push("revolute".to_string());
-                            }
-                            fixed_joint = false;
-                        },
-                        k::JointType::Linear { axis } => {
-                            lower_joint_limits.push(joint.limits.unwrap().min);
-                            upper_joint_limits.push(joint.limits.unwrap().max);
-                            joint_types.push("prismatic".to_string());
-                            fixed_joint = false;
-                        }
-                    }
-                    if !fixed_joint {
-                        num_dofs += 1;
-                        joint_names.push(joint.name.clone());
-                        if let Some(ordering) = &joint_ordering {
-                            if let Some(joint_index) = ordering.iter().position(|s| *s == joint.name) {
-                                joint_indices.push(joint_index);
-                            } else {
-                                println!("Warning: joint {} not found in joint_ordering provided!", joint.name)
-                            }
-                        } else {
-                            joint_indices.push(articulated_joint_index);
-                            articulated_joint_index += 1;
-                        }
-                    }
+                if !matches!(joint.joint_type, k::JointType::Fixed) {
+                    joints.push(resolve_mimic(&urdf_joints, &joint.name));
                 }
-
             });
-            let arm: arm_kinematics::ArmKineamtics = arm_kinematics::ArmKineamtics::from_a_series_chain(&serial_chain, false);
-            arms.push(arm.clone());
-            chain_indices.push(joint_indices);
-        }
-
-        // parse joint velocity limit
-        for i in 0..joint_names.len() {
-            let joint_name = joint_names[i].clone();
-            for j in 0..description.joints.len() {
-                if description.joints[j].name == joint_name {
-                    joint_velocity_limits.push(description.joints[j].limit.velocity);
-                    break;
+            arms.push(arm_kinematics::ArmKineamtics::from_a_series_chain(&serial_chain, false));
+            chain_joints.push(joints);
+        }
+
+        let joint_names: Vec<String> = match joint_ordering {
+            Some(ordering) => ordering,
+            None => {
+                let mut names: Vec<String> = Vec::new();
+                for (name, _, _) in chain_joints.iter().flatten() {
+                    if !names.contains(name) {
+                        names.push(name.clone());
+                    }
                 }
+                names
             }
-        }
+        };
+        let num_dofs = joint_names.len();
 
-        println!("Lower_joint_limits: {:?}", lower_joint_limits);
-        println!("Upper_joint_limits: {:?}", upper_joint_limits);
-        println!("Joint_velocity_limits: {:?}", joint_velocity_limits);
+        let mut chain_indices = Vec::new();
+        let mut chain_mimic = Vec::new();
+        for joints in &chain_joints {
+            chain_indices.push(joints.iter().map(|(name, _, _)| joint_names.iter().position(|s| s == name)
+                .unwrap_or_else(|| panic!("Joint {} not found in joint_ordering provided!", name))).collect::<Vec<usize>>());
+            chain_mimic.push(joints.iter().map(|(_, multiplier, offset)| (*multiplier, *offset)).collect::<Vec<(f64, f64)>>());
+        }
 
-        // Update the number of dofs if joint ordering is provided
-        if let Some(ordering) = joint_ordering {
-            num_dofs = ordering.len();
-            let mut lower_joint_limits_new = Vec::new();
-            let mut upper_joint_limits_new = Vec::new();
-            ordering.iter().for_each(|name| {
-                if let Some(joint_index) = joint_names.iter().position(|s| *s == *name) {
-                    lower_joint_limits_new.push(lower_joint_limits[joint_index]);
-                    upper_joint_limits_new.push(upper_joint_limits[joint_index]);
-                }
-            });
-            lower_joint_limits = lower_joint_limits_new;
-            upper_joint_limits = upper_joint_limits_new;   
+        let mut joints = Vec::new();
+        for (dof, name) in joint_names.iter().enumerate() {
+            let joint = urdf_joints.get(name.as_str()).unwrap_or_else(|| panic!("Unknown joint {} in joint_ordering", name));
+            let (joint_type, lower_limit, upper_limit) = match joint.joint_type {
+                urdf_rs::JointType::Continuous => ("continuous", -999.0, 999.0),
+                urdf_rs::JointType::Prismatic => ("prismatic", joint.limit.lower, joint.limit.upper),
+                _ => ("revolute", joint.limit.lower, joint.limit.upper)
+            };
+            let chains = (0..num_chains).filter(|&i| chain_indices[i].contains(&dof)).collect();
+            // a missing velocity limit reads as 0, which would not let the joint move at all
+            let velocity_limit = if joint.limit.velocity > 0.0 {
+                joint.limit.velocity
+            } else {
+                println!("Warning: joint {} has a velocity limit of 0 in the URDF, treating it as unlimited unless velocity_limits sets it", name);
+                f64::INFINITY
+            };
+            // URDF has no acceleration limits
+            joints.push(JointInfo{name: name.clone(), joint_type: joint_type.to_string(), lower_limit, upper_limit,
+                velocity_limit, acceleration_limit: f64::INFINITY, effort_limit: joint.limit.effort, chains});
         }
 
-        assert!(num_dofs == lower_joint_limits.len());
-        assert!(num_dofs == upper_joint_limits.len());
-        assert!(num_dofs == joint_names.len());
-        assert!(num_dofs == joint_types.len());
-        assert!(num_dofs == joint_velocity_limits.len());
+        println!("Lower_joint_limits: {:?}", joints.iter().map(|joint| joint.lower_limit).collect::<Vec<f64>>());
+        println!("Upper_joint_limits: {:?}", joints.iter().map(|joint| joint.upper_limit).collect::<Vec<f64>>());
+        println!("Joint_velocity_limits: {:?}", joints.iter().map(|joint| joint.velocity_limit).collect::<Vec<f64>>());
+
+        assert!(num_dofs == joints.len());
         assert!(num_chains == arms.len());
         assert!(num_chains == chain_indices.len());
 
         println!("Robot created successfully! Number of chains: {}, Number of dofs: {}", num_chains, num_dofs);
 
-        RobotKinematics{arms, num_chains, chain_indices, num_dofs, lower_joint_limits, upper_joint_limits, joint_velocity_limits, joint_names, joint_types}
+        RobotKinematics{arms, num_chains, chain_indices, chain_mimic, num_dofs, joints}
+
+    }
+
+    // Replaces the limits of the named joints, then scales the limits of all joints
+    pub fn set_joint_limits(&mut self, velocity_limits: &[(String, f64)], acceleration_limits: &[(String, f64)], velocity_scaling: f64, acceleration_scaling: f64) {
+        assert!(velocity_scaling > 0.0 && acceleration_scaling > 0.0, "Limit scaling should be positive");
+        for (name, limit) in velocity_limits {
+            self.__joint_mut(name, "velocity_limits").velocity_limit = *limit;
+        }
+        for (name, limit) in acceleration_limits {
+            self.__joint_mut(name, "acceleration_limits").acceleration_limit = *limit;
+        }
+        for joint in self.joints.iter_mut() {
+            joint.velocity_limit *= velocity_scaling;
+            joint.acceleration_limit *= acceleration_scaling;
+        }
+    }
+
+    fn __joint_mut(&mut self, name: &str, setting: &str) -> &mut JointInfo {
+        self.joints.iter_mut().find(|joint| joint.name == name).unwrap_or_else(|| panic!("Unknown joint {} in {}", name, setting))
+    }
+
+    pub fn joint_names(&self) -> Vec<String> {
+        self.joints.iter().map(|joint| joint.name.clone()).collect()
+    }
+
+    pub fn lower_joint_limits(&self) -> Vec<f64> {
+        self.joints.iter().map(|joint| joint.lower_limit).collect()
+    }
+
+    pub fn upper_joint_limits(&self) -> Vec<f64> {
+        self.joints.iter().map(|joint| joint.upper_limit).collect()
+    }
+
+    // Values of the joints of a chain, from the dofs of the robot
+    pub fn get_chain_values<T: Real>(&self, x: &[T], chain_idx: usize) -> Vec<T> {
+        self.chain_indices[chain_idx].iter().zip(&self.chain_mimic[chain_idx])
+            .map(|(&i, &(multiplier, offset))| x[i] * multiplier + offset).collect()
+    }
+
+    // Same base pose in the world frame for every chain
+    pub fn set_base_pose(&mut self, base_pose: nalgebra::Isometry3<f64>) {
+        for arm in self.arms.iter_mut() {
+            arm.base_pose = base_pose;
+        }
+    }
 
+    // Same tool center point for every chain, in the frame of its end effector link
+    pub fn set_tcp_offset(&mut self, tcp_offset: Option<nalgebra::Isometry3<f64>>) {
+        for arm in self.arms.iter_mut() {
+            arm.tcp_offset = tcp_offset;
+        }
     }
 
     pub fn get_frames_immutable(&self, x: &[f64]) -> Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)> {
         let mut out: Vec<(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>)> = Vec::new();
         for i in 0..self.num_chains {            
-            let chain_values: Vec<f64> = self.chain_indices[i].iter().map(|&i| x[i]).collect();
+            let chain_values: Vec<f64> = self.get_chain_values(x, i);
             out.push( self.arms[i].get_frames_immutable(chain_values.as_slice()) );
         }
         out
     }
+
+    // Frames of the links of all chains by URDF link name, in chain order, without repeating links
+    // that several chains share. With a tcp_offset, given in the end effector frame, or with the
+    // tcp_offset of the arm, each chain also gets a "<ee link>_tcp" frame after its end effector.
+    // The tcp_offset argument takes precedence over the one of the arm.
+    pub fn get_named_frames_immutable(&self, x: &[f64], tcp_offset: Option<&nalgebra::Isometry3<f64>>) -> Vec<NamedFrame> {
+        let mut buffers = (Vec::new(), Vec::new(), Vec::new());
+        let mut out = Vec::new();
+        self.__named_frames_into(x, tcp_offset, &mut buffers, &mut out);
+        out
+    }
+
+    // Frame of one URDF link, or None if no chain goes through it
+    pub fn get_link_frame_immutable(&self, x: &[f64], link_name: &str) -> Option<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)> {
+        for i in 0..self.num_chains {
+            if let Some(frame_idx) = self.arms[i].link_names.iter().position(|name| name == link_name) {
+                let chain_values: Vec<f64> = self.get_chain_values(x, i);
+                let (positions, rot_quats) = self.arms[i].get_frames_immutable(&chain_values);
+                return Some((positions[frame_idx], rot_quats[frame_idx]));
+            }
+        }
+        None
+    }
+
+    // get_named_frames_immutable at every sample of a motion, with the time of the sample
+    pub fn get_named_frames_batch(&self, motion: &Motion, tcp_offset: Option<&nalgebra::Isometry3<f64>>) -> Vec<(f64, Vec<NamedFrame>)> {
+        let mut buffers = (Vec::new(), Vec::new(), Vec::new());
+        motion.data.iter().map(|(t, config)| {
+            let mut frames = Vec::new();
+            self.__named_frames_into(config.as_slice().unwrap(), tcp_offset, &mut buffers, &mut frames);
+            (*t, frames)
+        }).collect()
+    }
+
+    // buffers are the chain values, positions and rotations, reused between calls
+    fn __named_frames_into(&self, x: &[f64], tcp_offset: Option<&nalgebra::Isometry3<f64>>,
+        buffers: &mut (Vec<f64>, Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>),
+        out: &mut Vec<NamedFrame>) {
+        let (chain_values, positions, rot_quats) = buffers;
+        for i in 0..self.num_chains {
+            chain_values.clear();
+            chain_values.extend(self.chain_indices[i].iter().zip(&self.chain_mimic[i])
+                .map(|(&i, &(multiplier, offset))| x[i] * multiplier + offset));
+            self.arms[i].get_frames_into(chain_values, positions, rot_quats);
+            let link_names = &self.arms[i].link_names;
+            for (j, name) in link_names.iter().enumerate() {
+                // the movable base dofs have no links
+                if !name.is_empty() && !out.iter().any(|(frame_name, _, _)| frame_name == name) {
+                    out.push((name.clone(), positions[j], rot_quats[j]));
+                }
+            }
+            if let Some(tcp_offset) = tcp_offset.or(self.arms[i].tcp_offset.as_ref()) {
+                let ee_idx = link_names.len() - 1;
+                out.push((format!("{}_tcp", link_names[link_names.len() - 1]),
+                    positions[ee_idx] + rot_quats[ee_idx] * tcp_offset.translation.vector, rot_quats[ee_idx] * tcp_offset.rotation));
+            }
+        }
+    }
+
+    pub fn get_frames_generic<T: Real>(&self, x: &[T]) -> Vec<(Vec<nalgebra::Vector3<T>>, Vec<Quat<T>>)> {
+        let mut out: Vec<(Vec<nalgebra::Vector3<T>>, Vec<Quat<T>>)> = Vec::new();
+        for i in 0..self.num_chains {
+            let chain_values: Vec<T> = self.get_chain_values(x, i);
+            out.push( self.arms[i].get_frames_generic(chain_values.as_slice()) );
+        }
+        out
+    }
     
+    // Jacobian of the end effector of one chain with respect to all global dofs
+    pub fn get_chain_jacobian_immutable(&self, x: &[f64], chain_idx: usize) -> nalgebra::DMatrix<f64> {
+        let chain_values: Vec<f64> = self.get_chain_values(x, chain_idx);
+        let chain_jacobian = self.arms[chain_idx].get_jacobian_immutable(chain_values.as_slice());
+        self.__expand_chain_jacobian(&chain_jacobian, chain_idx, x.len())
+    }
+
+    // Same as get_chain_jacobian_immutable, from the frames of the chain returned by get_frames_immutable
+    pub fn get_chain_jacobian_from_frames(&self, frames: &(Vec<nalgebra::Vector3<f64>>, Vec<nalgebra::UnitQuaternion<f64>>), chain_idx: usize, num_dofs: usize) -> nalgebra::DMatrix<f64> {
+        let chain_jacobian = self.arms[chain_idx].get_jacobian_from_frames(&frames.0, &frames.1);
+        self.__expand_chain_jacobian(&chain_jacobian, chain_idx, num_dofs)
+    }
+
+    fn __expand_chain_jacobian(&self, chain_jacobian: &nalgebra::DMatrix<f64>, chain_idx: usize, num_dofs: usize) -> nalgebra::DMatrix<f64> {
+        let mut jacobian = nalgebra::DMatrix::zeros(6, num_dofs);
+        for (j, &dof) in self.chain_indices[chain_idx].iter().enumerate() {
+            let multiplier = self.chain_mimic[chain_idx][j].0;
+            for row in 0..6 {
+                jacobian[(row, dof)] += multiplier * chain_jacobian[(row, j)];
+            }
+        }
+        jacobian
+    }
+
+    // Whole-robot jacobian: rows 6*i..6*i+6 hold the end effector jacobian of chain i, columns are
+    // the global dofs given by chain_indices
+    pub fn get_jacobian_immutable(&self, x: &[f64]) -> nalgebra::DMatrix<f64> {
+        let mut jacobian = nalgebra::DMatrix::zeros(6 * self.num_chains, x.len());
+        for i in 0..self.num_chains {
+            jacobian.rows_mut(6 * i, 6).copy_from(&self.get_chain_jacobian_immutable(x, i));
+        }
+        jacobian
+    }
+
     pub fn get_manipulability_immutable(&self, x: &[f64]) -> f64 {
         let mut out = 0.0;
         for i in 0..self.num_chains {            
-            let chain_values: Vec<f64> = self.chain_indices[i].iter().map(|&i| x[i]).collect();
+            let chain_values: Vec<f64> = self.get_chain_values(x, i);
             out += self.arms[i].get_manipulability_immutable( chain_values.as_slice() );
         }
         out
@@ -157,10 +297,27 @@ impl RobotKinematics {
     pub fn get_ee_pos_and_quat_immutable(&self, x: &[f64]) -> Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)> {
         let mut out: Vec<(nalgebra::Vector3<f64>, nalgebra::UnitQuaternion<f64>)> = Vec::new();
         for i in 0..self.num_chains {            
-            let chain_values: Vec<f64> = self.chain_indices[i].iter().map(|&i| x[i]).collect();
+            let chain_values: Vec<f64> = self.get_chain_values(x, i);
             out.push( self.arms[i].get_ee_pos_and_quat_immutable( chain_values.as_slice() ));
         }
         out
     }
 }
 
+// The joint that a mimic joint follows, through chains of mimic joints, with the multiplier and
+// offset of value = multiplier * value of that joint + offset
+fn resolve_mimic(urdf_joints: &HashMap<&str, &urdf_rs::Joint>, name: &str) -> (String, f64, f64) {
+    let (mut name, mut multiplier, mut offset) = (name.to_string(), 1.0, 0.0);
+    let mut visited = vec![name.clone()];
+    while let Some(mimic) = urdf_joints.get(name.as_str()).and_then(|joint| joint.mimic.as_ref()) {
+        if visited.contains(&mimic.joint) {
+            panic!("Cyclic mimic joints: {} -> {}", visited.join(" -> "), mimic.joint);
+        }
+        visited.push(mimic.joint.clone());
+        let mimic_multiplier = mimic.multiplier.unwrap_or(1.0);
+        offset += multiplier * mimic.offset.unwrap_or(0.0);
+        multiplier *= mimic_multiplier;
+        name = mimic.joint.clone();
+    }
+    (name, multiplier, offset)
+}
@@ -0,0 +1,317 @@
+use nalgebra::{Vector3, UnitQuaternion, Quaternion};
+use num::{Zero, One};
+use std::fmt::Debug;
+use std::ops::{Add, Sub, Mul, Div, Neg, AddAssign, SubAssign, MulAssign, DivAssign};
+
+// Forward-mode automatic differentiation. Kinematics and objectives are written once over a Real
+// scalar and evaluated either with f64 or with Dual, which carries the derivatives of a value with
+// respect to up to DUAL_WIDTH seeded joint values.
+
+pub const DUAL_WIDTH: usize = 8;
+
+pub trait Real: 'static + Copy + Debug + PartialEq + PartialOrd + Zero + One
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self>
+    + AddAssign + SubAssign + MulAssign + DivAssign
+    + Add<f64, Output = Self> + Sub<f64, Output = Self> + Mul<f64, Output = Self> + Div<f64, Output = Self> {
+    fn from_f64(val: f64) -> Self;
+    fn value(&self) -> f64;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn acos(self) -> Self;
+    fn atan2(self, other: Self) -> Self;
+    fn sqrt(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn powf(self, n: f64) -> Self;
+    fn abs(self) -> Self;
+    fn max(self, other: Self) -> Self {
+        if self.value() >= other.value() { self } else { other }
+    }
+    fn min(self, other: Self) -> Self {
+        if self.value() <= other.value() { self } else { other }
+    }
+}
+
+impl Real for f64 {
+    fn from_f64(val: f64) -> Self { val }
+    fn value(&self) -> f64 { *self }
+    fn sin(self) -> Self { f64::sin(self) }
+    fn cos(self) -> Self { f64::cos(self) }
+    fn tan(self) -> Self { f64::tan(self) }
+    fn acos(self) -> Self { f64::acos(self) }
+    fn atan2(self, other: Self) -> Self { f64::atan2(self, other) }
+    fn sqrt(self) -> Self { f64::sqrt(self) }
+    fn exp(self) -> Self { f64::exp(self) }
+    fn ln(self) -> Self { f64::ln(self) }
+    fn powi(self, n: i32) -> Self { f64::powi(self, n) }
+    fn powf(self, n: f64) -> Self { f64::powf(self, n) }
+    fn abs(self) -> Self { f64::abs(self) }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Dual {
+    pub re: f64,
+    pub eps: [f64; DUAL_WIDTH],
+}
+
+impl Dual {
+    pub fn constant(re: f64) -> Self {
+        Dual { re, eps: [0.0; DUAL_WIDTH] }
+    }
+
+    // a variable whose derivative is tracked in slot idx
+    pub fn variable(re: f64, idx: usize) -> Self {
+        let mut eps = [0.0; DUAL_WIDTH];
+        eps[idx] = 1.0;
+        Dual { re, eps }
+    }
+
+    // f(self) given f and its derivative at self.re
+    fn chain(self, f: f64, df: f64) -> Self {
+        let mut eps = self.eps;
+        for e in eps.iter_mut() {
+            *e *= df;
+        }
+        Dual { re: f, eps }
+    }
+}
+
+// Seeds x[offset..offset + DUAL_WIDTH] as variables, all other values are constants
+pub fn seed_duals(x: &[f64], offset: usize) -> Vec<Dual> {
+    x.iter().enumerate().map(|(i, &val)| {
+        if i >= offset && i < offset + DUAL_WIDTH {
+            Dual::variable(val, i - offset)
+        } else {
+            Dual::constant(val)
+        }
+    }).collect()
+}
+
+// Comparisons only look at the value, the same way branches in f64 code would
+impl PartialEq for Dual {
+    fn eq(&self, other: &Self) -> bool { self.re == other.re }
+}
+
+impl PartialOrd for Dual {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> { self.re.partial_cmp(&other.re) }
+}
+
+impl Add for Dual {
+    type Output = Dual;
+    fn add(self, rhs: Dual) -> Dual {
+        let mut eps = self.eps;
+        for i in 0..DUAL_WIDTH {
+            eps[i] += rhs.eps[i];
+        }
+        Dual { re: self.re + rhs.re, eps }
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+    fn sub(self, rhs: Dual) -> Dual {
+        let mut eps = self.eps;
+        for i in 0..DUAL_WIDTH {
+            eps[i] -= rhs.eps[i];
+        }
+        Dual { re: self.re - rhs.re, eps }
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+    fn mul(self, rhs: Dual) -> Dual {
+        let mut eps = [0.0; DUAL_WIDTH];
+        for i in 0..DUAL_WIDTH {
+            eps[i] = self.eps[i] * rhs.re + self.re * rhs.eps[i];
+        }
+        Dual { re: self.re * rhs.re, eps }
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+    fn div(self, rhs: Dual) -> Dual {
+        let inv = 1.0 / rhs.re;
+        let mut eps = [0.0; DUAL_WIDTH];
+        for i in 0..DUAL_WIDTH {
+            eps[i] = (self.eps[i] * rhs.re - self.re * rhs.eps[i]) * inv * inv;
+        }
+        Dual { re: self.re * inv, eps }
+    }
+}
+
+impl Neg for Dual {
+    type Output = Dual;
+    fn neg(self) -> Dual {
+        self.chain(-self.re, -1.0)
+    }
+}
+
+impl Add<f64> for Dual {
+    type Output = Dual;
+    fn add(self, rhs: f64) -> Dual { Dual { re: self.re + rhs, eps: self.eps } }
+}
+
+impl Sub<f64> for Dual {
+    type Output = Dual;
+    fn sub(self, rhs: f64) -> Dual { Dual { re: self.re - rhs, eps: self.eps } }
+}
+
+impl Mul<f64> for Dual {
+    type Output = Dual;
+    fn mul(self, rhs: f64) -> Dual { self.chain(self.re * rhs, rhs) }
+}
+
+impl Div<f64> for Dual {
+    type Output = Dual;
+    fn div(self, rhs: f64) -> Dual { self.chain(self.re / rhs, 1.0 / rhs) }
+}
+
+impl AddAssign for Dual {
+    fn add_assign(&mut self, rhs: Dual) { *self = *self + rhs; }
+}
+
+impl SubAssign for Dual {
+    fn sub_assign(&mut self, rhs: Dual) { *self = *self - rhs; }
+}
+
+impl MulAssign for Dual {
+    fn mul_assign(&mut self, rhs: Dual) { *self = *self * rhs; }
+}
+
+impl DivAssign for Dual {
+    fn div_assign(&mut self, rhs: Dual) { *self = *self / rhs; }
+}
+
+impl Zero for Dual {
+    fn zero() -> Self { Dual::constant(0.0) }
+    fn is_zero(&self) -> bool { self.re == 0.0 && self.eps.iter().all(|e| *e == 0.0) }
+}
+
+impl One for Dual {
+    fn one() -> Self { Dual::constant(1.0) }
+}
+
+impl Real for Dual {
+    fn from_f64(val: f64) -> Self { Dual::constant(val) }
+    fn value(&self) -> f64 { self.re }
+    fn sin(self) -> Self { self.chain(self.re.sin(), self.re.cos()) }
+    fn cos(self) -> Self { self.chain(self.re.cos(), -self.re.sin()) }
+    fn tan(self) -> Self {
+        let t = self.re.tan();
+        self.chain(t, 1.0 + t * t)
+    }
+    fn acos(self) -> Self { self.chain(self.re.acos(), -1.0 / (1.0 - self.re * self.re).sqrt()) }
+    fn atan2(self, other: Self) -> Self {
+        let d = self.re * self.re + other.re * other.re;
+        let mut eps = [0.0; DUAL_WIDTH];
+        for i in 0..DUAL_WIDTH {
+            eps[i] = (other.re * self.eps[i] - self.re * other.eps[i]) / d;
+        }
+        Dual { re: self.re.atan2(other.re), eps }
+    }
+    fn sqrt(self) -> Self {
+        let s = self.re.sqrt();
+        self.chain(s, 0.5 / s)
+    }
+    fn exp(self) -> Self {
+        let e = self.re.exp();
+        self.chain(e, e)
+    }
+    fn ln(self) -> Self { self.chain(self.re.ln(), 1.0 / self.re) }
+    fn powi(self, n: i32) -> Self {
+        if n == 0 {
+            return Dual::constant(1.0);
+        }
+        self.chain(self.re.powi(n), n as f64 * self.re.powi(n - 1))
+    }
+    fn powf(self, n: f64) -> Self { self.chain(self.re.powf(n), n * self.re.powf(n - 1.0)) }
+    fn abs(self) -> Self {
+        if self.re >= 0.0 { self } else { -self }
+    }
+}
+
+pub fn vec3_from_f64<T: Real>(v: &Vector3<f64>) -> Vector3<T> {
+    Vector3::new(T::from_f64(v.x), T::from_f64(v.y), T::from_f64(v.z))
+}
+
+pub fn vec3_value<T: Real>(v: &Vector3<T>) -> Vector3<f64> {
+    Vector3::new(v.x.value(), v.y.value(), v.z.value())
+}
+
+pub fn vec3_norm<T: Real>(v: &Vector3<T>) -> T {
+    v.dot(v).sqrt()
+}
+
+// Unit quaternion over a Real scalar, following the conventions of nalgebra's UnitQuaternion
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat<T: Real> {
+    pub w: T,
+    pub i: T,
+    pub j: T,
+    pub k: T,
+}
+
+impl<T: Real> Quat<T> {
+    pub fn new(w: T, i: T, j: T, k: T) -> Self {
+        Quat { w, i, j, k }
+    }
+
+    pub fn identity() -> Self {
+        Quat::new(T::one(), T::zero(), T::zero(), T::zero())
+    }
+
+    pub fn from_unit_quaternion(q: &UnitQuaternion<f64>) -> Self {
+        Quat::new(T::from_f64(q.w), T::from_f64(q.i), T::from_f64(q.j), T::from_f64(q.k))
+    }
+
+    pub fn from_axis_angle(axis: &Vector3<f64>, angle: T) -> Self {
+        let half = angle * 0.5;
+        let s = half.sin();
+        Quat::new(half.cos(), s * axis.x, s * axis.y, s * axis.z)
+    }
+
+    pub fn imag(&self) -> Vector3<T> {
+        Vector3::new(self.i, self.j, self.k)
+    }
+
+    pub fn inverse(&self) -> Self {
+        Quat::new(self.w, -self.i, -self.j, -self.k)
+    }
+
+    pub fn mul(&self, rhs: &Quat<T>) -> Self {
+        Quat::new(
+            self.w * rhs.w - self.i * rhs.i - self.j * rhs.j - self.k * rhs.k,
+            self.w * rhs.i + self.i * rhs.w + self.j * rhs.k - self.k * rhs.j,
+            self.w * rhs.j - self.i * rhs.k + self.j * rhs.w + self.k * rhs.i,
+            self.w * rhs.k + self.i * rhs.j - self.j * rhs.i + self.k * rhs.w)
+    }
+
+    pub fn rotate(&self, v: &Vector3<T>) -> Vector3<T> {
+        let q = self.imag();
+        let t = q.cross(v) * T::from_f64(2.0);
+        v + t * self.w + q.cross(&t)
+    }
+
+    // Rotation vector of the shortest rotation, like UnitQuaternion::scaled_axis
+    pub fn scaled_axis(&self) -> Vector3<T> {
+        let (w, q) = if self.w.value() < 0.0 { (-self.w, -self.imag()) } else { (self.w, self.imag()) };
+        let sin_half = vec3_norm(&q);
+        if sin_half.value() < 1e-9 {
+            return q * T::from_f64(2.0);
+        }
+        let angle = sin_half.atan2(w) * 2.0;
+        q * (angle / sin_half)
+    }
+
+    pub fn angle(&self) -> T {
+        vec3_norm(&self.scaled_axis())
+    }
+
+    pub fn value(&self) -> UnitQuaternion<f64> {
+        UnitQuaternion::new_unchecked(Quaternion::new(self.w.value(), self.i.value(), self.j.value(), self.k.value()))
+    }
+}
@@ -1,3 +1,5 @@
 pub mod transformations;
 pub mod file_utils;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod yaml_utils;
+pub mod autodiff;
@@ -1,4 +1,4 @@
-use nalgebra::{Vector3, UnitQuaternion, Quaternion, Vector4};
+use nalgebra::{Vector3, UnitQuaternion, Quaternion, Vector4, Matrix3};
 
 pub fn quaternion_log(q: UnitQuaternion<f64>) -> Vector3<f64> {
     let mut out_vec: Vector3<f64> = Vector3::new(q.i, q.j, q.k);
@@ -36,6 +36,18 @@ pub fn angle_between_quaternion(q: UnitQuaternion<f64>, q_prime: UnitQuaternion<
     quaternion_disp(q, q_prime).norm() * 2.0
 }
 
+// Maps a left perturbation of the rotation with rotation vector phi to the change of phi
+pub fn so3_left_jacobian_inverse(phi: &Vector3<f64>) -> Matrix3<f64> {
+    let theta = phi.norm();
+    let phi_hat = phi.cross_matrix();
+    let c = if theta < 1e-6 {
+        1.0 / 12.0
+    } else {
+        1.0 / theta.powi(2) - 1.0 / (2.0 * theta * (theta / 2.0).tan())
+    };
+    Matrix3::identity() - 0.5 * phi_hat + c * phi_hat * phi_hat
+}
+
 pub fn quaternion_from_angleaxis(angle: f64, axis: Vector3<f64>) -> UnitQuaternion<f64> {
     let a = angle * 2.0;
     let axis_len = axis.norm();
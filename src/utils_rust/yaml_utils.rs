@@ -0,0 +1,296 @@
+use yaml_rust::Yaml;
+use nalgebra::{Isometry3, Translation3, UnitQuaternion};
+use crate::groove::objective::{GrooveParams, SwampParams};
+
+pub fn yaml_to_f64(value: &Yaml) -> Option<f64> {
+    match value {
+        Yaml::Real(_) => value.as_f64(),
+        Yaml::Integer(i) => Some(*i as f64),
+        _ => None
+    }
+}
+
+pub fn yaml_to_vec3(value: &Yaml, default: [f64; 3]) -> [f64; 3] {
+    if let Some(arr) = value.as_vec() {
+        assert!(arr.len() == 3, "Expected a list of 3 numbers, got {:?}", value);
+        let mut out = [0.0; 3];
+        for i in 0..3 {
+            out[i] = yaml_to_f64(&arr[i]).unwrap();
+        }
+        out
+    } else {
+        default
+    }
+}
+
+// {translation: [x, y, z], rotation: [roll, pitch, yaw]}, both optional, or None if value is missing
+pub fn yaml_to_pose(value: &Yaml) -> Option<Isometry3<f64>> {
+    if value.is_badvalue() || value.is_null() {
+        return None;
+    }
+    let t = yaml_to_vec3(&value["translation"], [0.0, 0.0, 0.0]);
+    let r = yaml_to_vec3(&value["rotation"], [0.0, 0.0, 0.0]);
+    Some(Isometry3::from_parts(Translation3::new(t[0], t[1], t[2]), UnitQuaternion::from_euler_angles(r[0], r[1], r[2])))
+}
+
+#[derive(Clone, Debug)]
+pub struct CuboidObstacle {
+    pub name: String,
+    pub x_halflength: f64,
+    pub y_halflength: f64,
+    pub z_halflength: f64,
+    pub tx: f64,
+    pub ty: f64,
+    pub tz: f64,
+    pub rx: f64,
+    pub ry: f64,
+    pub rz: f64,
+    pub is_dynamic: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SphereObstacle {
+    pub name: String,
+    pub radius: f64,
+    pub tx: f64,
+    pub ty: f64,
+    pub tz: f64,
+    pub is_dynamic: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PointCloudObstacle {
+    pub name: String,
+    pub points: Vec<[f64; 3]>,
+    pub tx: f64,
+    pub ty: f64,
+    pub tz: f64,
+    pub rx: f64,
+    pub ry: f64,
+    pub rz: f64,
+    pub is_dynamic: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnvCollisionFileParser {
+    pub robot_link_radius: f64,
+    pub cuboids: Vec<CuboidObstacle>,
+    pub spheres: Vec<SphereObstacle>,
+    pub pcds: Vec<PointCloudObstacle>,
+}
+
+impl EnvCollisionFileParser {
+    // Parses the `obstacles` section of a settings file, e.g.
+    // obstacles:
+    //   cuboids:
+    //     - name: table
+    //       half_extents: [0.6, 0.4, 0.02]
+    //       translation: [0.5, 0.0, -0.02]
+    //       rotation: [0.0, 0.0, 0.0]  # roll, pitch, yaw
+    //   spheres:
+    //     - name: ball
+    //       radius: 0.1
+    //       translation: [0.4, 0.3, 0.5]
+    //   point_clouds:  # approximated by the convex hull of the points
+    //     - name: workpiece
+    //       points: [[0.0, 0.0, 0.0], [0.1, 0.0, 0.0], [0.0, 0.1, 0.0], [0.0, 0.0, 0.1]]
+    //       translation: [0.6, 0.0, 0.2]
+    //       is_dynamic: true
+    pub fn from_yaml(settings: &Yaml) -> Self {
+        let robot_link_radius = yaml_to_f64(&settings["link_radius"]).unwrap_or(0.05);
+        let obstacles = &settings["obstacles"];
+
+        let mut cuboids: Vec<CuboidObstacle> = Vec::new();
+        if let Some(arr) = obstacles["cuboids"].as_vec() {
+            for (i, item) in arr.iter().enumerate() {
+                let half_extents = yaml_to_vec3(&item["half_extents"], [0.0, 0.0, 0.0]);
+                let t = yaml_to_vec3(&item["translation"], [0.0, 0.0, 0.0]);
+                let r = yaml_to_vec3(&item["rotation"], [0.0, 0.0, 0.0]);
+                cuboids.push(CuboidObstacle {
+                    name: Self::parse_name(item, "cuboid", i),
+                    x_halflength: half_extents[0], y_halflength: half_extents[1], z_halflength: half_extents[2],
+                    tx: t[0], ty: t[1], tz: t[2],
+                    rx: r[0], ry: r[1], rz: r[2],
+                    is_dynamic: item["is_dynamic"].as_bool().unwrap_or(false),
+                });
+            }
+        }
+
+        let mut spheres: Vec<SphereObstacle> = Vec::new();
+        if let Some(arr) = obstacles["spheres"].as_vec() {
+            for (i, item) in arr.iter().enumerate() {
+                let t = yaml_to_vec3(&item["translation"], [0.0, 0.0, 0.0]);
+                spheres.push(SphereObstacle {
+                    name: Self::parse_name(item, "sphere", i),
+                    radius: yaml_to_f64(&item["radius"]).expect("Sphere obstacle requires a radius"),
+                    tx: t[0], ty: t[1], tz: t[2],
+                    is_dynamic: item["is_dynamic"].as_bool().unwrap_or(false),
+                });
+            }
+        }
+
+        let mut pcds: Vec<PointCloudObstacle> = Vec::new();
+        if let Some(arr) = obstacles["point_clouds"].as_vec() {
+            for (i, item) in arr.iter().enumerate() {
+                let t = yaml_to_vec3(&item["translation"], [0.0, 0.0, 0.0]);
+                let r = yaml_to_vec3(&item["rotation"], [0.0, 0.0, 0.0]);
+                let points: Vec<[f64; 3]> = item["points"].as_vec().expect("Point cloud obstacle requires points")
+                    .iter().map(|p| yaml_to_vec3(p, [0.0, 0.0, 0.0])).collect();
+                pcds.push(PointCloudObstacle {
+                    name: Self::parse_name(item, "point_cloud", i),
+                    points,
+                    tx: t[0], ty: t[1], tz: t[2],
+                    rx: r[0], ry: r[1], rz: r[2],
+                    is_dynamic: item["is_dynamic"].as_bool().unwrap_or(false),
+                });
+            }
+        }
+
+        Self{robot_link_radius, cuboids, spheres, pcds}
+    }
+
+    fn parse_name(item: &Yaml, prefix: &str, idx: usize) -> String {
+        match item["name"].as_str() {
+            Some(name) => name.to_string(),
+            None => format!("{}_{}", prefix, idx)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjectiveSpec {
+    pub kind: String,
+    pub weight: f64,
+    // overrides the default loss shape of the objective when set
+    pub groove: Option<GrooveParams>,
+    pub swamp: Option<SwampParams>,
+}
+
+impl ObjectiveSpec {
+    pub fn new(kind: &str, weight: f64) -> Self {
+        Self{kind: kind.to_string(), weight, groove: None, swamp: None}
+    }
+
+    // The objectives RelaxedIK uses when the settings file does not list any
+    pub fn default_relaxed_ik(options: &ObjectiveOptions) -> Vec<Self> {
+        let mut specs = vec![Self::new("ee_position_dofs", 50.0), Self::new("ee_rotation_dofs", 1.0),
+            Self::new("env_collision", 1.0), Self::new("velocity", 0.01)];
+        Self::push_optional(&mut specs, options);
+        specs
+    }
+
+    pub fn default_standard_ik() -> Vec<Self> {
+        vec![Self::new("ee_position", 10.0), Self::new("ee_orientation", 1.0)]
+    }
+
+    // Objectives for pointing a sensor at goal_look_at_points instead of matching full poses
+    pub fn look_at_relaxed_ik(options: &ObjectiveOptions) -> Vec<Self> {
+        let mut specs = vec![Self::new("look_at", 10.0), Self::new("look_at_distance", 10.0), Self::new("view_cone", 10.0),
+            Self::new("env_collision", 1.0), Self::new("velocity", 0.01)];
+        Self::push_optional(&mut specs, options);
+        specs
+    }
+
+    pub fn look_at_standard_ik() -> Vec<Self> {
+        vec![Self::new("look_at", 10.0), Self::new("look_at_distance", 10.0), Self::new("view_cone", 10.0)]
+    }
+
+    fn push_optional(specs: &mut Vec<Self>, options: &ObjectiveOptions) {
+        if options.self_collision {
+            specs.push(Self::new("self_collision", 1.0));
+        }
+        if options.joint_limit_avoidance {
+            specs.push(Self::new("joint_limits", 0.1));
+        }
+        if options.posture {
+            specs.push(Self::new("posture", 0.01));
+        }
+    }
+}
+
+// Optional objectives added to the default relaxed_ik objectives
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ObjectiveOptions {
+    pub self_collision: bool,
+    pub joint_limit_avoidance: bool,
+    pub posture: bool,
+}
+
+impl ObjectiveOptions {
+    pub fn from_yaml(settings: &Yaml) -> Self {
+        Self{
+            self_collision: settings["self_collision_objective"].as_bool().unwrap_or(false),
+            joint_limit_avoidance: settings["joint_limit_avoidance"].as_bool().unwrap_or(false),
+            posture: settings["posture_objective"].as_bool().unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjectiveFileParser {
+    pub relaxed_ik: Vec<ObjectiveSpec>,
+    pub standard_ik: Vec<ObjectiveSpec>,
+}
+
+impl ObjectiveFileParser {
+    // Parses the `objectives` section of a settings file, e.g.
+    // objectives:
+    //   relaxed_ik:
+    //     - kind: ee_position_dofs
+    //       weight: 50.0
+    //       groove: {d: 2, c: 0.1, f: 10.0, g: 2}
+    //     - kind: joint_limits
+    //       weight: 0.01
+    //       swamp: {f1: 10.0, f2: 10.0, p1: 20}
+    //   standard_ik:
+    //     - kind: ee_position
+    //       weight: 10.0
+    // A list that is left out keeps the default objectives.
+    pub fn from_yaml(settings: &Yaml, options: &ObjectiveOptions) -> Self {
+        let objectives = &settings["objectives"];
+        let relaxed_ik = match objectives["relaxed_ik"].as_vec() {
+            Some(arr) => arr.iter().map(Self::parse_spec).collect(),
+            None => ObjectiveSpec::default_relaxed_ik(options)
+        };
+        let standard_ik = match objectives["standard_ik"].as_vec() {
+            Some(arr) => arr.iter().map(Self::parse_spec).collect(),
+            None => ObjectiveSpec::default_standard_ik()
+        };
+        Self{relaxed_ik, standard_ik}
+    }
+
+    fn parse_spec(item: &Yaml) -> ObjectiveSpec {
+        let kind = item["kind"].as_str().expect("Objective requires a kind");
+        let weight = yaml_to_f64(&item["weight"]).unwrap_or_else(|| panic!("Objective {} requires a weight", kind));
+        let groove = if item["groove"].is_badvalue() {
+            None
+        } else {
+            let g = &item["groove"];
+            Some(GrooveParams{
+                d: Self::parse_int(&g["d"], kind, "groove.d"),
+                c: Self::parse_f64(&g["c"], kind, "groove.c"),
+                f: Self::parse_f64(&g["f"], kind, "groove.f"),
+                g: Self::parse_int(&g["g"], kind, "groove.g"),
+            })
+        };
+        let swamp = if item["swamp"].is_badvalue() {
+            None
+        } else {
+            let sw = &item["swamp"];
+            Some(SwampParams{
+                f1: Self::parse_f64(&sw["f1"], kind, "swamp.f1"),
+                f2: Self::parse_f64(&sw["f2"], kind, "swamp.f2"),
+                p1: Self::parse_int(&sw["p1"], kind, "swamp.p1"),
+            })
+        };
+        ObjectiveSpec{kind: kind.to_string(), weight, groove, swamp}
+    }
+
+    fn parse_f64(value: &Yaml, kind: &str, key: &str) -> f64 {
+        yaml_to_f64(value).unwrap_or_else(|| panic!("Objective {} requires a number for {}", kind, key))
+    }
+
+    fn parse_int(value: &Yaml, kind: &str, key: &str) -> i32 {
+        value.as_i64().unwrap_or_else(|| panic!("Objective {} requires an integer for {}", kind, key)) as i32
+    }
+}